@@ -0,0 +1,279 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use dtp_utils::results::{sha256_hex, write_json};
+
+use crate::listen;
+
+// how often the children are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How to run one server/client pair
+#[derive(Clone, Debug)]
+pub struct BenchOptions {
+    /// the dtp config replayed by the server
+    pub config: String,
+    pub server_bin: String,
+    pub client_bin: String,
+    pub emu_bin: String,
+    /// options of link_emu, `None` connects the client to the server directly
+    pub emu_args: Option<Vec<String>>,
+    pub server_args: Vec<String>,
+    pub client_args: Vec<String>,
+    pub addr: String,
+    /// 0 picks a free port
+    pub port: u16,
+    pub output_dir: PathBuf,
+    /// longest wait for the server and link_emu to listen
+    pub ready_timeout: Duration,
+    /// longest run of the client
+    pub timeout: Duration,
+    /// longest wait for the server to exit after the client
+    pub grace: Duration,
+}
+
+/// A child process, killed when dropped if it is still running
+struct Process {
+    name: &'static str,
+    command: Vec<String>,
+    child: Child,
+    started: Instant,
+    status: Option<ExitStatus>,
+    killed: bool,
+    duration: Duration,
+}
+
+/// What happened to a process, in bench.json
+#[derive(Debug, Serialize)]
+pub struct ProcessResult {
+    pub name: &'static str,
+    pub command: Vec<String>,
+    pub exit_code: Option<i32>,
+    /// killed by dtp_bench
+    pub killed: bool,
+    /// seconds
+    pub duration: f64,
+}
+
+/// bench.json
+#[derive(Debug, Serialize)]
+pub struct BenchResult {
+    pub config: String,
+    pub config_sha256: Option<String>,
+    pub output_dir: String,
+    pub ok: bool,
+    pub error: Option<String>,
+    pub processes: Vec<ProcessResult>,
+}
+
+impl Process {
+    /// Start `bin` with its stdout and stderr in `dir`
+    fn spawn(name: &'static str, bin: &str, args: Vec<String>, dir: &Path) -> Result<Self, Box<dyn Error>> {
+        fs::create_dir_all(dir)?;
+        let mut command = vec![bin.to_string()];
+        command.extend(args);
+        info!("start {}: {}", name, command.join(" "));
+        let child = Command::new(bin)
+            .args(&command[1..])
+            .stdin(Stdio::null())
+            .stdout(File::create(dir.join("stdout.log"))?)
+            .stderr(File::create(dir.join("stderr.log"))?)
+            .spawn()
+            .map_err(|e| format!("couldn't start {} ({}): {}", name, bin, e))?;
+        Ok(Process {
+            name,
+            command,
+            child,
+            started: Instant::now(),
+            status: None,
+            killed: false,
+            duration: Duration::default(),
+        })
+    }
+
+    /// Whether the process has exited, without waiting
+    fn exited(&mut self) -> Result<bool, Box<dyn Error>> {
+        if self.status.is_none() {
+            if let Some(status) = self.child.try_wait()? {
+                debug!("{} exited: {}", self.name, status);
+                self.status = Some(status);
+                self.duration = self.started.elapsed();
+            }
+        }
+        Ok(self.status.is_some())
+    }
+
+    /// Wait for the process to exit for at most `timeout`
+    fn wait_timeout(&mut self, timeout: Duration) -> Result<bool, Box<dyn Error>> {
+        let start = Instant::now();
+        while !self.exited()? {
+            if start.elapsed() >= timeout {
+                return Ok(false);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        Ok(true)
+    }
+
+    /// Wait until something listens on `port`
+    fn wait_listening(&mut self, port: u16, timeout: Duration) -> Result<(), Box<dyn Error>> {
+        let start = Instant::now();
+        while !listen::listening(port) {
+            if self.exited()? {
+                return Err(format!("{} exited before listening on port {}", self.name, port).into());
+            }
+            if start.elapsed() >= timeout {
+                return Err(format!("{} is not listening on port {} after {:?}", self.name, port, timeout).into());
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        debug!("{} is listening on port {} after {:?}", self.name, port, start.elapsed());
+        Ok(())
+    }
+
+    fn kill(&mut self) {
+        if self.exited().unwrap_or(false) {
+            return;
+        }
+        info!("kill {}", self.name);
+        let _ = self.child.kill();
+        self.killed = true;
+        self.status = self.child.wait().ok();
+        self.duration = self.started.elapsed();
+    }
+
+    fn succeeded(&self) -> bool {
+        !self.killed && self.status.is_some_and(|status| status.success())
+    }
+
+    fn result(&self) -> ProcessResult {
+        ProcessResult {
+            name: self.name,
+            command: self.command.clone(),
+            exit_code: self.status.and_then(|status| status.code()),
+            killed: self.killed,
+            duration: self.duration.as_secs_f64(),
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+fn free_port(addr: &str) -> Result<u16, Box<dyn Error>> {
+    Ok(TcpListener::bind((addr, 0))?.local_addr()?.port())
+}
+
+/// Split options given as one string like `--bandwidth 10 --delay 20`
+pub fn split_args(args: &str) -> Vec<String> {
+    args.split_whitespace().map(String::from).collect()
+}
+
+/// Run the server, link_emu and the client, the processes are pushed to
+/// `processes` as they are started
+fn run(opts: &BenchOptions, processes: &mut Vec<Process>) -> Result<(), Box<dyn Error>> {
+    let addr = opts.addr.as_str();
+    let port = match opts.port {
+        0 => free_port(addr)?,
+        port => port,
+    };
+    let dir = |name: &str| -> PathBuf { opts.output_dir.join(name) };
+    let path = |name: &str| -> String { dir(name).to_string_lossy().into_owned() };
+
+    let mut server_args = vec![
+        "--output-dir".to_string(), path("server"),
+        "--results-json".to_string(), "results.json".to_string(),
+    ];
+    server_args.extend(opts.server_args.iter().cloned());
+    server_args.extend(vec![addr.to_string(), port.to_string(), opts.config.clone()]);
+    processes.push(Process::spawn("server", &opts.server_bin, server_args, &dir("server"))?);
+    processes.last_mut().unwrap().wait_listening(port, opts.ready_timeout)?;
+
+    let client_port = match opts.emu_args {
+        None => port,
+        Some(ref args) => {
+            let emu_port = free_port(addr)?;
+            let mut emu_args = args.clone();
+            emu_args.extend(vec![addr.to_string(), emu_port.to_string(), addr.to_string(), port.to_string()]);
+            processes.push(Process::spawn("link_emu", &opts.emu_bin, emu_args, &dir("link_emu"))?);
+            processes.last_mut().unwrap().wait_listening(emu_port, opts.ready_timeout)?;
+            emu_port
+        },
+    };
+
+    let mut client_args = vec![
+        "--output-dir".to_string(), path("client"),
+        "--results-json".to_string(), "results.json".to_string(),
+        "--trace".to_string(), opts.config.clone(),
+    ];
+    client_args.extend(opts.client_args.iter().cloned());
+    client_args.extend(vec![addr.to_string(), client_port.to_string()]);
+    processes.push(Process::spawn("client", &opts.client_bin, client_args, &dir("client"))?);
+
+    let client = processes.last_mut().unwrap();
+    if !client.wait_timeout(opts.timeout)? {
+        client.kill();
+        return Err(format!("client did not finish in {:?}", opts.timeout).into());
+    }
+    if !client.succeeded() {
+        return Err(format!("client failed: {}", client.status.unwrap()).into());
+    }
+
+    let server = &mut processes[0];
+    if !server.wait_timeout(opts.grace)? {
+        server.kill();
+        return Err(format!("server did not exit {:?} after the client", opts.grace).into());
+    }
+    if !server.succeeded() {
+        return Err(format!("server failed: {}", server.status.unwrap()).into());
+    }
+    if let Some(emu) = processes.iter_mut().find(|p| p.name == "link_emu") {
+        // link_emu runs until it is killed, exiting before is an error
+        if emu.exited()? {
+            return Err(format!("link_emu exited during the run: {}", emu.status.unwrap()).into());
+        }
+        emu.kill();
+    }
+    Ok(())
+}
+
+/// Run one server/client pair, every process is gone when it returns and
+/// the result is also written to `bench.json` of the output directory
+pub fn run_bench(opts: &BenchOptions) -> BenchResult {
+    let mut processes = Vec::new();
+    let res = match fs::read(&opts.config) {
+        Ok(_) => run(opts, &mut processes),
+        Err(e) => Err(format!("couldn't read {}: {}", opts.config, e).into()),
+    };
+    for process in processes.iter_mut() {
+        process.kill();
+    }
+    let result = BenchResult {
+        config: opts.config.clone(),
+        config_sha256: fs::read(&opts.config).ok().map(|data| sha256_hex(&data)),
+        output_dir: opts.output_dir.to_string_lossy().into_owned(),
+        ok: res.is_ok(),
+        error: res.err().map(|e| e.to_string()),
+        processes: processes.iter().map(Process::result).collect(),
+    };
+    let path = opts.output_dir.join("bench.json");
+    if let Err(e) = write_json(&path, &result) {
+        warn!("couldn't write {}: {}", path.display(), e);
+    }
+    result
+}
+
+/// The results.json written by the client of a run
+pub fn client_results(output_dir: &Path) -> Option<serde_json::Value> {
+    serde_json::from_slice(&fs::read(output_dir.join("client/results.json")).ok()?).ok()
+}
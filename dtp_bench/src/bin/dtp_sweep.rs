@@ -0,0 +1,281 @@
+#[macro_use]
+extern crate log;
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use dtp_bench::bench::{client_results, run_bench, split_args, BenchOptions};
+use dtp_bench::stats::Summary;
+use dtp_utils::results::create_output;
+
+const USAGE: &str = "Usage:
+dtp_sweep [options] MATRIX
+dtp_sweep -h | --help
+
+Run every combination of a JSON matrix like
+{
+  \"traces\": [\"aitrans-server/trace/block_trace/aitrans_block.txt\"],
+  \"cc\": [\"reno\", \"cubic\"],
+  \"policies\": {\"fifo\": \"\", \"priority\": \"--scheduler priority --drop-expired\"},
+  \"links\": {\"loopback\": null, \"10mbit\": \"--bandwidth 10 --delay 20\"},
+  \"repetitions\": 5
+}
+one after another on loopback. A policy is a set of extra server options
+like --scheduler and --drop-expired, a link is the options of link_emu,
+null connects the client directly.
+
+Options:
+--output-dir DIR         Directory of the runs, runs.csv and summary.csv [default: sweep].
+--server-bin PATH        The server binary [default: tcp_server/target/release/tcp_server].
+--client-bin PATH        The client binary [default: tcp_client/target/release/tcp_client].
+--emu-bin PATH           The link_emu binary [default: link_emu/target/release/link_emu].
+--client-args ARGS       Extra options of the client.
+--timeout SECS           Longest run of the client [default: 120].
+--dry-run                Only print the runs.
+-h --help                Show this screen.
+";
+
+/// Metrics of a run taken from the results of the client and the server
+const METRICS: [&str; 6] = ["qoe", "weighted_hit_ratio", "on_time_byte_ratio", "goodput", "bct_p50", "bct_p99"];
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Matrix {
+    traces: Vec<String>,
+    #[serde(default = "default_cc")]
+    cc: Vec<String>,
+    #[serde(default = "default_policies")]
+    policies: BTreeMap<String, String>,
+    #[serde(default = "default_links")]
+    links: BTreeMap<String, Option<String>>,
+    #[serde(default = "default_repetitions")]
+    repetitions: usize,
+}
+
+fn default_cc() -> Vec<String> {
+    vec!["reno".to_string()]
+}
+
+fn default_policies() -> BTreeMap<String, String> {
+    vec![("default".to_string(), String::new())].into_iter().collect()
+}
+
+fn default_links() -> BTreeMap<String, Option<String>> {
+    vec![("loopback".to_string(), None)].into_iter().collect()
+}
+
+fn default_repetitions() -> usize {
+    1
+}
+
+/// One cell of the matrix, repeated `repetitions` times
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+struct Combination {
+    trace: String,
+    cc: String,
+    policy: String,
+    link: String,
+}
+
+impl Combination {
+    fn trace_name(&self) -> String {
+        match Path::new(&self.trace).file_stem() {
+            Some(stem) => stem.to_string_lossy().into_owned(),
+            None => self.trace.clone(),
+        }
+    }
+
+    fn dir(&self, output_dir: &Path, rep: usize) -> PathBuf {
+        output_dir.join(self.trace_name()).join(&self.cc).join(&self.policy).join(&self.link).join(rep.to_string())
+    }
+
+    fn csv(&self) -> String {
+        format!("{},{},{},{}", self.trace_name(), self.cc, self.policy, self.link)
+    }
+}
+
+// the runs of a combination are in a directory named after it, so two
+// traces of the same file name would share their runs
+fn combinations(matrix: &Matrix) -> Result<Vec<Combination>, String> {
+    let mut names: BTreeMap<String, &str> = BTreeMap::new();
+    for trace in matrix.traces.iter() {
+        let name = Combination { trace: trace.clone(), ..Combination::default() }.trace_name();
+        if let Some(other) = names.insert(name.clone(), trace) {
+            return Err(format!("traces {} and {} have the same name {}, rename one of them", other, trace, name));
+        }
+    }
+    for (i, cc) in matrix.cc.iter().enumerate() {
+        if matrix.cc[..i].contains(cc) {
+            return Err(format!("cc {} is listed twice", cc));
+        }
+    }
+    let mut ret = Vec::new();
+    for trace in matrix.traces.iter() {
+        for cc in matrix.cc.iter() {
+            for policy in matrix.policies.keys() {
+                for link in matrix.links.keys() {
+                    ret.push(Combination {
+                        trace: trace.clone(),
+                        cc: cc.clone(),
+                        policy: policy.clone(),
+                        link: link.clone(),
+                    });
+                }
+            }
+        }
+    }
+    Ok(ret)
+}
+
+// `METRICS` of a finished run
+fn metrics(output_dir: &Path) -> Option<Vec<f64>> {
+    let results = client_results(output_dir)?;
+    let seconds = results["stats"]["total_time"].as_f64()? / 1e6;
+    let goodput = if seconds > 0.0 { results["stats"]["good_bytes"].as_f64()? / seconds } else { 0.0 };
+    Some(vec![
+        results["qoe"]["qoe"].as_f64()?,
+        results["qoe"]["weighted_hit_ratio"].as_f64()?,
+        results["qoe"]["on_time_byte_ratio"].as_f64()?,
+        goodput,
+        results["latency"]["all"]["p50"].as_f64().unwrap_or(0.0),
+        results["latency"]["all"]["p99"].as_f64().unwrap_or(0.0),
+    ])
+}
+
+// a CSV field, empty for `None`
+fn opt(value: Option<f64>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = docopt::Docopt::new(USAGE)
+    .and_then(|dopt| dopt.parse())
+    .unwrap_or_else(|e| e.exit());
+
+    env_logger::builder()
+    .format_timestamp_nanos()
+    .filter_level(log::LevelFilter::Info)
+    .parse_default_env()
+    .init();
+
+    let matrix: Matrix = serde_json::from_slice(&fs::read(args.get_str("MATRIX"))?)
+        .map_err(|e| format!("invalid matrix {}: {}", args.get_str("MATRIX"), e))?;
+    let output_dir = PathBuf::from(args.get_str("--output-dir"));
+    let combinations = combinations(&matrix)?;
+    let runs = combinations.len() * matrix.repetitions;
+
+    if args.get_bool("--dry-run") {
+        for rep in 0..matrix.repetitions {
+            for c in combinations.iter() {
+                println!("{},{}", c.csv(), rep);
+            }
+        }
+        return Ok(());
+    }
+
+    let mut runs_csv = create_output(&output_dir.join("runs.csv"))?;
+    writeln!(runs_csv, "trace,cc,policy,link,rep,ok,{},output_dir", METRICS.join(","))?;
+    let mut samples: BTreeMap<Combination, Vec<Vec<f64>>> = BTreeMap::new();
+    let mut failed: BTreeMap<Combination, usize> = BTreeMap::new();
+    let mut done = 0;
+    // repetitions are the outer loop, so that a drift of the host over
+    // time is spread over all the combinations
+    for rep in 0..matrix.repetitions {
+        for c in combinations.iter() {
+            done += 1;
+            info!("run {}/{}: {} rep {}", done, runs, c.csv(), rep);
+            let mut server_args = vec!["--cc-algorithm".to_string(), c.cc.clone()];
+            server_args.extend(split_args(&matrix.policies[&c.policy]));
+            let opts = BenchOptions {
+                config: c.trace.clone(),
+                server_bin: args.get_str("--server-bin").to_string(),
+                client_bin: args.get_str("--client-bin").to_string(),
+                emu_bin: args.get_str("--emu-bin").to_string(),
+                emu_args: matrix.links[&c.link].as_deref().map(split_args),
+                server_args,
+                client_args: split_args(args.get_str("--client-args")),
+                addr: "127.0.0.1".to_string(),
+                port: 0,
+                output_dir: c.dir(&output_dir, rep),
+                ready_timeout: Duration::from_secs(10),
+                timeout: Duration::from_secs_f64(args.get_str("--timeout").parse()?),
+                grace: Duration::from_secs(5),
+            };
+            let result = run_bench(&opts);
+            let values = if result.ok { metrics(&opts.output_dir) } else { None };
+            let values_csv = match values {
+                Some(ref values) => values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(","),
+                None => vec![""; METRICS.len()].join(","),
+            };
+            writeln!(runs_csv, "{},{},{},{},{}", c.csv(), rep, values.is_some(), values_csv, opts.output_dir.display())?;
+            match values {
+                Some(values) => samples.entry(c.clone()).or_default().push(values),
+                None => {
+                    warn!("run failed: {}", result.error.unwrap_or_else(|| "no client results".to_string()));
+                    *failed.entry(c.clone()).or_default() += 1;
+                },
+            }
+        }
+    }
+
+    let mut summary_csv = create_output(&output_dir.join("summary.csv"))?;
+    writeln!(summary_csv, "trace,cc,policy,link,metric,n,failed,mean,std,ci95_low,ci95_high")?;
+    println!("{:<40}\t{:>4}\t{:>6}\t{:>20}\t{:>24}", "trace,cc,policy,link", "n", "failed", "qoe", "goodput(B/s)");
+    for c in combinations.iter() {
+        let runs = samples.get(c).cloned().unwrap_or_default();
+        let fails = failed.get(c).copied().unwrap_or(0);
+        let mut summaries = Vec::new();
+        for (i, metric) in METRICS.iter().enumerate() {
+            let values: Vec<f64> = runs.iter().map(|values| values[i]).collect();
+            let s = Summary::of(&values);
+            // empty fields rather than zeros when every run failed or there is no interval
+            let fields = match s {
+                Some(ref s) => format!("{},{},{},{}", s.mean, s.std, opt(s.ci95_low), opt(s.ci95_high)),
+                None => ",,,".to_string(),
+            };
+            writeln!(summary_csv, "{},{},{},{},{}", c.csv(), metric, values.len(), fails, fields)?;
+            summaries.push(s);
+        }
+        let pm = |s: &Option<Summary>| match s {
+            Some(Summary { mean, ci95_high: Some(high), .. }) => format!("{:.4} ± {:.4}", mean, high - mean),
+            Some(Summary { mean, .. }) => format!("{:.4}", mean),
+            None => "-".to_string(),
+        };
+        println!("{:<40}\t{:>4}\t{:>6}\t{:>20}\t{:>24}", c.csv(), runs.len(), fails, pm(&summaries[0]), pm(&summaries[3]));
+    }
+    println!("results in {}", output_dir.display());
+    if !failed.is_empty() {
+        return Err(format!("{} of {} runs failed", failed.values().sum::<usize>(), runs).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matrix() {
+        let matrix: Matrix = serde_json::from_str(r#"{
+            "traces": ["a/b.txt", "c.txt"],
+            "cc": ["reno", "cubic"],
+            "links": {"lan": null, "slow": "--bandwidth 1"},
+            "repetitions": 3
+        }"#).unwrap();
+        let all = combinations(&matrix).unwrap();
+        assert_eq!(8, all.len());
+        assert_eq!("b,reno,default,lan", all[0].csv());
+        assert_eq!(Path::new("out/c/cubic/default/slow/2"), all[7].dir(Path::new("out"), 2));
+        assert!(serde_json::from_str::<Matrix>(r#"{"traces": [], "links2": {}}"#).is_err());
+        let same_name: Matrix = serde_json::from_str(r#"{"traces": ["a/x.txt", "b/x.txt"]}"#).unwrap();
+        assert!(combinations(&same_name).is_err());
+        let same_cc: Matrix = serde_json::from_str(r#"{"traces": ["x.txt"], "cc": ["reno", "reno"]}"#).unwrap();
+        assert!(combinations(&same_cc).is_err());
+    }
+}
@@ -0,0 +1,6 @@
+#[macro_use]
+extern crate log;
+
+pub mod bench;
+mod listen;
+pub mod stats;
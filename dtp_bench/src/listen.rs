@@ -0,0 +1,58 @@
+use std::fs;
+
+// `st` of a listening socket in /proc/net/tcp
+const TCP_LISTEN: &str = "0A";
+
+/// Whether a socket listens on TCP `port`, from the content of
+/// `/proc/net/tcp` or `/proc/net/tcp6`
+pub fn listening_in(table: &str, port: u16) -> bool {
+    table.lines().skip(1).any(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 || fields[3] != TCP_LISTEN {
+            return false;
+        }
+        match fields[1].rsplit(':').next().map(|p| u16::from_str_radix(p, 16)) {
+            Some(Ok(p)) => p == port,
+            _ => false,
+        }
+    })
+}
+
+/// Whether any process listens on TCP `port`
+///
+/// Connecting to find out is not an option, the server takes the first
+/// connection for the client.
+pub fn listening(port: u16) -> bool {
+    ["/proc/net/tcp", "/proc/net/tcp6"].iter().any(|path| match fs::read_to_string(path) {
+        Ok(table) => listening_in(&table, port),
+        Err(_) => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE: &str = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 0100007F:15B3 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 123 1 0000000000000000 100 0 0 10 0
+   1: 0100007F:15B4 0100007F:D2F0 01 00000000:00000000 00:00000000 00000000     0        0 124 1 0000000000000000 20 4 30 10 -1
+";
+
+    #[test]
+    fn listen_table() {
+        assert!(listening_in(TABLE, 5555));
+        // established, not listening
+        assert!(!listening_in(TABLE, 5556));
+        assert!(!listening_in(TABLE, 80));
+        assert!(!listening_in("", 5555));
+    }
+
+    #[test]
+    fn listen_socket() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(listening(port));
+        drop(listener);
+        assert!(!listening(port));
+    }
+}
@@ -0,0 +1,95 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use dtp_bench::bench::{client_results, run_bench, split_args, BenchOptions};
+
+const USAGE: &str = "Usage:
+dtp_bench [options] CONFIG
+dtp_bench -h | --help
+
+Options:
+--server-bin PATH        The server binary [default: tcp_server/target/release/tcp_server].
+--client-bin PATH        The client binary [default: tcp_client/target/release/tcp_client].
+--emu-bin PATH           The link_emu binary [default: link_emu/target/release/link_emu].
+--emu ARGS               Put link_emu with these options between the client and the server, like --emu=\"--bandwidth 10 --delay 20\".
+--server-args ARGS       Extra options of the server, like --server-args=\"--pcap server.pcapng\".
+--client-args ARGS       Extra options of the client.
+--addr ADDR              Address the server listens on [default: 127.0.0.1].
+--port PORT              Port of the server, 0 picks a free one [default: 0].
+--output-dir DIR         Directory of the results of both sides [default: results].
+--ready-timeout SECS     Longest wait for the server and link_emu to listen [default: 10].
+--timeout SECS           Longest run of the client [default: 120].
+--grace SECS             Longest wait for the server to exit after the client [default: 5].
+-h --help                Show this screen.
+";
+
+fn secs(args: &docopt::ArgvMap, option: &str) -> Result<Duration, Box<dyn Error>> {
+    Ok(Duration::from_secs_f64(args.get_str(option).parse()?))
+}
+
+fn options(args: &docopt::ArgvMap) -> Result<BenchOptions, Box<dyn Error>> {
+    Ok(BenchOptions {
+        config: args.get_str("CONFIG").to_string(),
+        server_bin: args.get_str("--server-bin").to_string(),
+        client_bin: args.get_str("--client-bin").to_string(),
+        emu_bin: args.get_str("--emu-bin").to_string(),
+        emu_args: match args.get_str("--emu") {
+            "" => None,
+            emu => Some(split_args(emu)),
+        },
+        server_args: split_args(args.get_str("--server-args")),
+        client_args: split_args(args.get_str("--client-args")),
+        addr: args.get_str("--addr").to_string(),
+        port: args.get_str("--port").parse()?,
+        output_dir: PathBuf::from(args.get_str("--output-dir")),
+        ready_timeout: secs(args, "--ready-timeout")?,
+        timeout: secs(args, "--timeout")?,
+        grace: secs(args, "--grace")?,
+    })
+}
+
+// the key numbers of the client results
+fn client_summary(output_dir: &Path) -> Option<String> {
+    let results = client_results(output_dir)?;
+    Some(format!("blocks={}/{}, good_bytes={}, total_time={}, qoe={:.6}",
+        results["stats"]["blocks"],
+        results["qoe"]["blocks"],
+        results["stats"]["good_bytes"],
+        results["stats"]["total_time"],
+        results["qoe"]["qoe"].as_f64()?
+    ))
+}
+
+fn main() {
+    let args = docopt::Docopt::new(USAGE)
+    .and_then(|dopt| dopt.parse())
+    .unwrap_or_else(|e| e.exit());
+
+    env_logger::builder()
+    .format_timestamp_nanos()
+    .filter_level(log::LevelFilter::Info)
+    .parse_default_env()
+    .init();
+
+    let opts = match options(&args) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("invalid option: {}", e);
+            std::process::exit(2);
+        }
+    };
+    let result = run_bench(&opts);
+    match result.error {
+        None => {
+            if let Some(summary) = client_summary(&opts.output_dir) {
+                println!("{}", summary);
+            }
+            println!("results in {}", opts.output_dir.display());
+        },
+        Some(e) => {
+            eprintln!("bench failed: {}, see {}", e, opts.output_dir.display());
+            std::process::exit(1);
+        }
+    }
+}
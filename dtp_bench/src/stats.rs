@@ -0,0 +1,59 @@
+use serde::Serialize;
+
+/// Two-sided 95% critical values of Student's t for 1 to 30 degrees of freedom
+const T95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228,
+    2.201, 2.179, 2.160, 2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086,
+    2.080, 2.074, 2.069, 2.064, 2.060, 2.056, 2.052, 2.048, 2.045, 2.042,
+];
+const Z95: f64 = 1.960;
+
+/// Mean of repeated measurements with its 95% confidence interval
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Summary {
+    pub n: usize,
+    pub mean: f64,
+    /// sample standard deviation
+    pub std: f64,
+    /// `None` with a single sample
+    pub ci95_low: Option<f64>,
+    pub ci95_high: Option<f64>,
+}
+
+impl Summary {
+    /// `None` without any sample, a single sample has no interval
+    pub fn of(samples: &[f64]) -> Option<Summary> {
+        let n = samples.len();
+        if n == 0 {
+            return None;
+        }
+        let mean = samples.iter().sum::<f64>() / n as f64;
+        if n == 1 {
+            return Some(Summary { n, mean, std: 0.0, ci95_low: None, ci95_high: None });
+        }
+        let var = samples.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / (n - 1) as f64;
+        let std = var.sqrt();
+        let t = T95.get(n - 2).copied().unwrap_or(Z95);
+        let half = t * std / (n as f64).sqrt();
+        Some(Summary { n, mean, std, ci95_low: Some(mean - half), ci95_high: Some(mean + half) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary() {
+        assert_eq!(None, Summary::of(&[]));
+        assert_eq!(Some(Summary { n: 1, mean: 2.0, std: 0.0, ci95_low: None, ci95_high: None }), Summary::of(&[2.0]));
+        let s = Summary::of(&[1.0, 2.0, 3.0, 4.0]).unwrap();
+        assert!((s.mean - 2.5).abs() < 1e-9);
+        assert!((s.std - 1.290_994).abs() < 1e-6);
+        // t(3) = 3.182
+        assert!((s.ci95_high.unwrap() - (2.5 + 3.182 * 1.290_994 / 2.0)).abs() < 1e-5);
+        let many: Vec<f64> = (0..100).map(|i| (i % 2) as f64).collect();
+        let s = Summary::of(&many).unwrap();
+        assert!((s.ci95_high.unwrap() - s.mean - Z95 * s.std / 10.0).abs() < 1e-9);
+    }
+}
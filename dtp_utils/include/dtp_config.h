@@ -20,7 +20,7 @@ __uint64_t getCurrentUsec();  //usec
 struct dtp_config {
     int deadline;   // ms
     int priority;   //
-    int block_size; // byte
+    __uint64_t block_size; // byte
     float send_time_gap;//s
 };
 
@@ -0,0 +1,99 @@
+use std::error::Error;
+use std::io::{self, Write};
+
+use dtp_utils::trace::{validate, write_dtp_config};
+use dtp_utils::tracegen::{generate, Model};
+
+const USAGE: &str = "Usage:
+  trace_gen [options] MODEL...
+  trace_gen -h | --help
+
+Write a synthetic trace in the dtp_config format, the blocks of several
+models are merged by send time. Models:
+  video      I/P frames at --fps, an I frame (priority 2) every --gop frames (priority 1)
+  poisson    blocks of --size bytes arriving at --rate per second
+  onoff      poisson arrivals during on periods of mean --on s, silent for mean --off s
+  game       updates (priority 3) at --tick Hz, a snapshot (priority 2) every --snapshot ticks
+  telemetry  blocks of --size bytes every 1 / --rate s
+
+Options:
+  --duration SECS         Length of the trace [default: 10].
+  --seed SEED             Seed of the random numbers [default: 1].
+  --size-jitter FRACTION  Block sizes vary uniformly by this fraction [default: 0.2].
+  --deadline MS           Deadline of the blocks [default: 200].
+  --priority P            Priority of poisson, onoff and telemetry blocks [default: 1].
+  --fps FPS               Frame rate of video [default: 30].
+  --gop FRAMES            Frames of a GOP of video [default: 30].
+  --i-size BYTES          Size of an I frame [default: 60000].
+  --p-size BYTES          Size of a P frame [default: 8000].
+  --rate RATE             Blocks per second of poisson, onoff and telemetry [default: 20].
+  --size BYTES            Size of poisson, onoff and telemetry blocks [default: 10000].
+  --on SECS               Mean on period of onoff [default: 1].
+  --off SECS              Mean off period of onoff [default: 1].
+  --tick HZ               Update rate of game [default: 60].
+  --update-size BYTES     Size of a game update [default: 200].
+  --snapshot TICKS        Ticks between two game snapshots [default: 60].
+  --snapshot-size BYTES   Size of a game snapshot [default: 20000].
+  -o --output PATH        Write to a file instead of stdout.
+  -h --help               Show this screen.
+";
+
+fn main() -> Result<(), Box<dyn Error>> {
+  let args = docopt::Docopt::new(USAGE)
+    .and_then(|dopt| dopt.parse())
+    .unwrap_or_else(|e| e.exit());
+
+  let get = |option: &str| args.get_str(option);
+  let deadline = get("--deadline").parse()?;
+  let priority = get("--priority").parse()?;
+  let rate = get("--rate").parse()?;
+  let size = get("--size").parse()?;
+  let mut models = Vec::new();
+  for name in args.get_vec("MODEL") {
+    models.push(match name {
+      "video" => Model::Video {
+        fps: get("--fps").parse()?,
+        gop: get("--gop").parse()?,
+        i_size: get("--i-size").parse()?,
+        p_size: get("--p-size").parse()?,
+        i_priority: 2,
+        p_priority: 1,
+        deadline,
+      },
+      "poisson" => Model::Poisson { rate, size, priority, deadline },
+      "onoff" => Model::OnOff {
+        rate,
+        on: get("--on").parse()?,
+        off: get("--off").parse()?,
+        size,
+        priority,
+        deadline,
+      },
+      "game" => Model::Game {
+        tick: get("--tick").parse()?,
+        update_size: get("--update-size").parse()?,
+        snapshot: get("--snapshot").parse()?,
+        snapshot_size: get("--snapshot-size").parse()?,
+        update_priority: 3,
+        snapshot_priority: 2,
+        deadline,
+      },
+      "telemetry" => Model::Telemetry { rate, size, priority, deadline },
+      _ => return Err(format!("unknown model {}", name).into()),
+    });
+  }
+
+  let cfgs = generate(&models, get("--duration").parse()?, get("--size-jitter").parse()?, get("--seed").parse()?)?;
+  validate(&cfgs)?;
+  match get("--output") {
+    "" => {
+      let stdout = io::stdout();
+      let mut out = stdout.lock();
+      write_dtp_config(&mut out, &cfgs)?;
+      out.flush()?;
+    },
+    path => dtp_utils::trace::save_dtp_config(path, &cfgs)?,
+  }
+  eprintln!("{} blocks", cfgs.len());
+  Ok(())
+}
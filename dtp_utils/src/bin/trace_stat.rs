@@ -0,0 +1,60 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use dtp_utils::get_dtp_config;
+use dtp_utils::results::write_json;
+use dtp_utils::trace::validate;
+use dtp_utils::tracestat::TraceStats;
+
+const USAGE: &str = "Usage:
+  trace_stat [options] CONFIG
+  trace_stat -h | --help
+
+Report what a trace in the dtp_config format demands: its duration, the
+offered load over time, the block sizes by priority, the deadlines and the
+bandwidth each block needs to meet its deadline behind the blocks before it.
+
+Options:
+  --window SECS     Window of the offered load [default: 1].
+  --svg DIR         Write the plots load.svg, sizes.svg, deadlines.svg and required.svg to DIR.
+  --json PATH       Also write the statistics as JSON.
+  -h --help         Show this screen.
+";
+
+fn main() -> Result<(), Box<dyn Error>> {
+  let args = docopt::Docopt::new(USAGE)
+    .and_then(|dopt| dopt.parse())
+    .unwrap_or_else(|e| e.exit());
+
+  let config = args.get_str("CONFIG");
+  let window: f64 = args.get_str("--window").parse()?;
+  if !window.is_finite() || window <= 0.0 {
+    return Err(format!("invalid window {}", window).into());
+  }
+  if !Path::new(config).is_file() {
+    return Err(format!("couldn't read {}", config).into());
+  }
+  let cfgs = get_dtp_config(config);
+  if let Err(e) = validate(&cfgs) {
+    eprintln!("warning: {}", e);
+  }
+
+  let stats = TraceStats::compute(&cfgs, window);
+  print!("{}", stats.report());
+  match args.get_str("--svg") {
+    "" => {},
+    dir => {
+      let dir = Path::new(dir);
+      fs::create_dir_all(dir)?;
+      for (name, svg) in stats.plots(&cfgs) {
+        fs::write(dir.join(format!("{}.svg", name)), svg)?;
+      }
+    },
+  }
+  match args.get_str("--json") {
+    "" => {},
+    path => write_json(Path::new(path), &stats)?,
+  }
+  Ok(())
+}
@@ -0,0 +1,107 @@
+use std::error::Error;
+use std::io::{self, Write};
+use std::path::Path;
+
+use dtp_utils::get_dtp_config;
+use dtp_utils::results::create_output;
+use dtp_utils::trace::{save_dtp_config, validate, write_dtp_config};
+use dtp_utils::transform::{self, parse_rules, Field, Source};
+
+const USAGE: &str = "Usage:
+  trace_transform [options] CONFIG...
+  trace_transform -h | --help
+
+Transform traces in the dtp_config format. Several traces are merged by
+absolute send time first, then the steps run in the order of the options
+below.
+
+Options:
+  --tags PATH         Write the source of every block as CSV: block,block_id,trace,source_block.
+  --start SECS        Keep the blocks sent from SECS on, moved to start at 0 [default: 0].
+  --end SECS          Keep the blocks sent before SECS.
+  --scale-gap F       Multiply the send time gaps, 2 is half speed [default: 1].
+  --scale-size F      Multiply the block sizes, 2 is double bitrate [default: 1].
+  --deadline RULES    Rewrite the deadlines, see below.
+  --priority RULES    Rewrite the priorities, see below.
+  --shuffle SEED      Shuffle the deadlines, sizes and priorities over the send times.
+  -o --output PATH    Write to a file instead of stdout.
+  -h --help           Show this screen.
+
+RULES are comma separated, each is =N, +N, -N or *F, optionally followed
+by @P to only apply to the blocks of priority P. A block is rewritten by
+the first rule that applies: the deadline rules '*0.5@2,-50' halve the
+deadlines of priority 2 and make the others 50ms shorter, the priority
+rules '=2@1,=1@2' swap priorities 1 and 2. Deadlines stay at least 1ms
+and priorities at least 0.
+";
+
+fn main() -> Result<(), Box<dyn Error>> {
+  let args = docopt::Docopt::new(USAGE)
+    .and_then(|dopt| dopt.parse())
+    .unwrap_or_else(|e| e.exit());
+
+  let mut traces = Vec::new();
+  for path in args.get_vec("CONFIG") {
+    if !Path::new(path).is_file() {
+      return Err(format!("couldn't read {}", path).into());
+    }
+    traces.push(get_dtp_config(path));
+  }
+  let (mut cfgs, mut sources) = transform::merge(&traces);
+
+  let start: f64 = args.get_str("--start").parse()?;
+  let end: f64 = match args.get_str("--end") {
+    "" => f64::INFINITY,
+    end => end.parse()?,
+  };
+  let range = transform::slice_range(&cfgs, start, end);
+  cfgs = transform::slice(&cfgs, start, end);
+  sources = sources[range].to_vec();
+
+  cfgs = transform::scale_gaps(&cfgs, args.get_str("--scale-gap").parse()?)?;
+  cfgs = transform::scale_sizes(&cfgs, args.get_str("--scale-size").parse()?)?;
+  match args.get_str("--deadline") {
+    "" => {},
+    rules => cfgs = transform::rewrite(&cfgs, Field::Deadline, &parse_rules(rules)?),
+  }
+  match args.get_str("--priority") {
+    "" => {},
+    rules => cfgs = transform::rewrite(&cfgs, Field::Priority, &parse_rules(rules)?),
+  }
+  match args.get_str("--shuffle") {
+    "" => {},
+    seed => {
+      let seed = seed.parse()?;
+      let order = transform::shuffle_order(cfgs.len(), seed);
+      cfgs = transform::shuffle(&cfgs, seed);
+      sources = order.iter().map(|i| sources[*i]).collect();
+    },
+  }
+  validate(&cfgs)?;
+
+  match args.get_str("--output") {
+    "" => {
+      let stdout = io::stdout();
+      let mut out = stdout.lock();
+      write_dtp_config(&mut out, &cfgs)?;
+      out.flush()?;
+    },
+    path => save_dtp_config(path, &cfgs)?,
+  }
+  match args.get_str("--tags") {
+    "" => {},
+    path => write_tags(Path::new(path), &sources)?,
+  }
+  eprintln!("{} blocks", cfgs.len());
+  Ok(())
+}
+
+// the server gives block i the id 4 * i + 5
+fn write_tags(path: &Path, sources: &[Source]) -> io::Result<()> {
+  let mut out = io::BufWriter::new(create_output(path)?);
+  writeln!(out, "block,block_id,trace,source_block")?;
+  for (i, source) in sources.iter().enumerate() {
+    writeln!(out, "{},{},{},{}", i, 4 * i + 5, source.trace, source.block)?;
+  }
+  out.flush()
+}
@@ -0,0 +1,98 @@
+/// CRC-32C (Castagnoli) polynomial, reversed
+const POLY: u32 = 0x82F6_3B78;
+
+// slicing-by-8: TABLES[k][b] is the CRC of byte b followed by k zero bytes
+const TABLES: [[u32; 256]; 8] = tables();
+
+const fn tables() -> [[u32; 256]; 8] {
+  let mut tables = [[0; 256]; 8];
+  let mut i = 0;
+  while i < 256 {
+    let mut crc = i as u32;
+    let mut bit = 0;
+    while bit < 8 {
+      crc = if crc & 1 == 1 { (crc >> 1) ^ POLY } else { crc >> 1 };
+      bit += 1;
+    }
+    tables[0][i] = crc;
+    i += 1;
+  }
+  let mut k = 1;
+  while k < 8 {
+    let mut i = 0;
+    while i < 256 {
+      let prev = tables[k - 1][i];
+      tables[k][i] = (prev >> 8) ^ tables[0][(prev & 0xff) as usize];
+      i += 1;
+    }
+    k += 1;
+  }
+  tables
+}
+
+/// Incremental CRC-32C of a byte stream
+#[derive(Clone, Copy, Debug)]
+pub struct Crc32c(u32);
+
+impl Default for Crc32c {
+  fn default() -> Self {
+    Crc32c::new()
+  }
+}
+
+impl Crc32c {
+  pub fn new() -> Self {
+    Crc32c(!0)
+  }
+
+  pub fn update(&mut self, data: &[u8]) {
+    let mut crc = self.0;
+    let mut chunks = data.chunks_exact(8);
+    for c in &mut chunks {
+      let lo = crc ^ u32::from_le_bytes([c[0], c[1], c[2], c[3]]);
+      crc = TABLES[7][(lo & 0xff) as usize]
+        ^ TABLES[6][((lo >> 8) & 0xff) as usize]
+        ^ TABLES[5][((lo >> 16) & 0xff) as usize]
+        ^ TABLES[4][(lo >> 24) as usize]
+        ^ TABLES[3][c[4] as usize]
+        ^ TABLES[2][c[5] as usize]
+        ^ TABLES[1][c[6] as usize]
+        ^ TABLES[0][c[7] as usize];
+    }
+    for b in chunks.remainder() {
+      crc = (crc >> 8) ^ TABLES[0][((crc ^ *b as u32) & 0xff) as usize];
+    }
+    self.0 = crc;
+  }
+
+  pub fn finish(&self) -> u32 {
+    !self.0
+  }
+}
+
+/// CRC-32C of `data`
+pub fn crc32c(data: &[u8]) -> u32 {
+  let mut crc = Crc32c::new();
+  crc.update(data);
+  crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn check() {
+    assert_eq!(0, crc32c(b""));
+    assert_eq!(0xE306_9283, crc32c(b"123456789"));
+    // RFC 3720 B.4
+    assert_eq!(0x8A91_36AA, crc32c(&[0; 32]));
+    assert_eq!(0x62A8_AB43, crc32c(&[0xff; 32]));
+    let data: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+    let mut crc = Crc32c::new();
+    for part in data.chunks(13) {
+      crc.update(part);
+    }
+    assert_eq!(crc32c(&data), crc.finish());
+  }
+}
@@ -22,7 +22,7 @@ struct dtp_config* parse_dtp_config(const char *filename,int *number)
     float send_time_gap;
     int deadline;
     int priority;
-    int block_size;
+    long long block_size;
 
     int cfgs_len = 0;
     static int max_cfgs_len = 10000;
@@ -38,10 +38,11 @@ struct dtp_config* parse_dtp_config(const char *filename,int *number)
         return NULL;
     }
 
-    while (fscanf(fd, "%f %d %d %d", &send_time_gap, &deadline, &block_size, &priority) == 4 && cfgs_len < 10000) {
+    while (fscanf(fd, "%f %d %lld %d", &send_time_gap, &deadline, &block_size, &priority) == 4 && cfgs_len < 10000) {
         cfgs[cfgs_len].send_time_gap = send_time_gap;
         cfgs[cfgs_len].deadline = deadline;
-        cfgs[cfgs_len].block_size = block_size;
+        // a negative size is read as 0, which is invalid as well
+        cfgs[cfgs_len].block_size = block_size < 0 ? 0 : block_size;
         cfgs[cfgs_len].priority = priority;
 
         cfgs_len ++;
@@ -0,0 +1,159 @@
+/// Marker written before every block header, so that the receiver can find the
+/// next header again after corrupted bytes
+pub const BLOCK_MAGIC: [u8; 4] = *b"DTPB";
+
+/// Marker of a header followed by the checksum of the payload
+pub const CHECKSUM_MAGIC: [u8; 4] = *b"DTPC";
+
+/// Marker of the message that ends an aborted run in place of a block header
+pub const ABORT_MAGIC: [u8; 4] = *b"DTPA";
+
+/// Size of the abort message, the magic then the unix time of the abort in
+/// us, big endian
+pub const ABORT_SIZE: usize = 12;
+
+/// Size of the block header on the wire, magic included
+pub const HEADER_SIZE: usize = 44;
+
+/// Size of a header with a checksum
+pub const MAX_HEADER_SIZE: usize = HEADER_SIZE + 4;
+
+/// The header sent in front of every block
+///
+/// | magic | id | start_timestamp (us) | block_size (B) | priority | deadline (ms) | checksum |
+/// | -- | -- | -- | -- | -- | -- | -- |
+/// | 4B | 8B | 8B | 8B | 8B | 8B | 4B |
+///
+/// All the integers are big endian. The checksum is the CRC-32C of the
+/// payload, only headers starting with `CHECKSUM_MAGIC` have it.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct BlockHeader {
+  pub id: u64,
+  pub start_timestamp: u64,
+  pub block_size: u64,
+  pub priority: u64,
+  pub deadline: u64,
+  pub checksum: Option<u32>,
+}
+
+/// Size of the header starting with `magic`, `None` if it is no block magic
+pub fn header_len(magic: &[u8]) -> Option<usize> {
+  match magic.get(0..4)? {
+    m if m == BLOCK_MAGIC => Some(HEADER_SIZE),
+    m if m == CHECKSUM_MAGIC => Some(MAX_HEADER_SIZE),
+    _ => None,
+  }
+}
+
+/// The message telling the peer that the run is aborted at `timestamp`
+pub fn abort_message(timestamp: u64) -> [u8; ABORT_SIZE] {
+  let mut msg = [0; ABORT_SIZE];
+  msg[0..4].copy_from_slice(&ABORT_MAGIC);
+  msg[4..12].copy_from_slice(&timestamp.to_be_bytes());
+  msg
+}
+
+/// The time of the abort if `bytes` start with an abort message
+pub fn parse_abort(bytes: &[u8]) -> Option<u64> {
+  if bytes.len() < ABORT_SIZE || bytes[0..4] != ABORT_MAGIC {
+    return None;
+  }
+  let mut timestamp = [0; 8];
+  timestamp.copy_from_slice(&bytes[4..12]);
+  Some(u64::from_be_bytes(timestamp))
+}
+
+impl BlockHeader {
+  /// Size of the header on the wire
+  pub fn wire_len(&self) -> usize {
+    match self.checksum {
+      Some(_) => MAX_HEADER_SIZE,
+      None => HEADER_SIZE,
+    }
+  }
+
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut hdr = vec![0; self.wire_len()];
+    match self.checksum {
+      Some(checksum) => {
+        hdr[0..4].copy_from_slice(&CHECKSUM_MAGIC);
+        hdr[44..48].copy_from_slice(&checksum.to_be_bytes());
+      },
+      None => hdr[0..4].copy_from_slice(&BLOCK_MAGIC),
+    }
+    hdr[4..12].copy_from_slice(&self.id.to_be_bytes());
+    hdr[12..20].copy_from_slice(&self.start_timestamp.to_be_bytes());
+    hdr[20..28].copy_from_slice(&self.block_size.to_be_bytes());
+    hdr[28..36].copy_from_slice(&self.priority.to_be_bytes());
+    hdr[36..44].copy_from_slice(&self.deadline.to_be_bytes());
+    hdr
+  }
+
+  /// Decode a header, `None` if it does not start with a block magic or
+  /// `hdr` is shorter than the header
+  pub fn from_bytes(hdr: &[u8]) -> Option<BlockHeader> {
+    let len = header_len(hdr)?;
+    if hdr.len() < len {
+      return None;
+    }
+    let field = |start: usize| {
+      let mut bytes = [0; 8];
+      bytes.copy_from_slice(&hdr[start..start + 8]);
+      u64::from_be_bytes(bytes)
+    };
+    Some(BlockHeader {
+      id: field(4),
+      start_timestamp: field(12),
+      block_size: field(20),
+      priority: field(28),
+      deadline: field(36),
+      checksum: if len == MAX_HEADER_SIZE {
+        Some(u32::from_be_bytes([hdr[44], hdr[45], hdr[46], hdr[47]]))
+      } else {
+        None
+      },
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn roundtrip() {
+    let hdr = BlockHeader {
+      id: 5,
+      start_timestamp: 1_600_000_000_000_000,
+      block_size: 1235,
+      priority: 1,
+      deadline: 200,
+      checksum: None,
+    };
+    let mut bytes = hdr.to_bytes();
+    assert_eq!(HEADER_SIZE, bytes.len());
+    assert_eq!(Some(hdr), BlockHeader::from_bytes(&bytes));
+    bytes[1] = 0;
+    assert_eq!(None, BlockHeader::from_bytes(&bytes));
+  }
+
+  #[test]
+  fn checksum() {
+    let hdr = BlockHeader { id: 9, block_size: 10, checksum: Some(0xE306_9283), ..BlockHeader::default() };
+    let bytes = hdr.to_bytes();
+    assert_eq!(MAX_HEADER_SIZE, bytes.len());
+    assert_eq!(Some(MAX_HEADER_SIZE), header_len(&bytes));
+    assert_eq!(Some(hdr), BlockHeader::from_bytes(&bytes));
+    assert_eq!(None, BlockHeader::from_bytes(&bytes[..HEADER_SIZE]));
+    assert_eq!(None, header_len(b"DTP"));
+  }
+
+  #[test]
+  fn abort() {
+    let msg = abort_message(1_600_000_000_000_000);
+    assert_eq!(Some(1_600_000_000_000_000), parse_abort(&msg));
+    assert_eq!(None, parse_abort(&msg[..ABORT_SIZE - 1]));
+    assert_eq!(None, header_len(&msg));
+    assert_eq!(None, BlockHeader::from_bytes(&msg));
+  }
+}
@@ -1,13 +1,31 @@
+#[macro_use]
+extern crate log;
+
 use std::{ffi::c_void, slice, ffi::CString};
 
 use libc::{free};
 
+mod header;
+pub use header::{
+  abort_message, header_len, parse_abort, BlockHeader, ABORT_MAGIC, ABORT_SIZE, BLOCK_MAGIC, CHECKSUM_MAGIC, HEADER_SIZE,
+  MAX_HEADER_SIZE,
+};
+pub mod crc32c;
+pub mod metrics;
+pub mod pcapng;
+pub mod results;
+pub mod svg;
+pub mod trace;
+pub mod tracegen;
+pub mod tracestat;
+pub mod transform;
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct dtp_config {
   pub deadline: i32, // in milliseconds
   pub priority: i32,
-  pub block_size: i32,  // in bytes
+  pub block_size: u64,  // in bytes
   pub send_time_gap: f32 // in seconds
 }
 
@@ -32,9 +50,9 @@ pub fn get_dtp_config(filename: &str) -> Vec<dtp_config> {
       let cfgs_vec = cfgs_slice.to_vec();
       // free the raw pointer
       free(cfgs_ptr as *mut c_void);
-      return cfgs_vec;
+      cfgs_vec
     } else {
-      return Vec::new();
+      Vec::new()
     }
   }
 }
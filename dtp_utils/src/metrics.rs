@@ -0,0 +1,172 @@
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Least time between two renderings in `publish_with`
+pub const PUBLISH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Serve Prometheus text exposition on `GET /metrics` from a side thread
+///
+/// The poll loop renders the metrics and publishes them, the thread only
+/// hands out the last published page, so it never touches the loop's state.
+pub struct MetricsExporter {
+  page: Arc<Mutex<String>>,
+  local_addr: SocketAddr,
+  last_publish: Option<Instant>,
+}
+
+impl MetricsExporter {
+  pub fn bind(addr: &str) -> io::Result<Self> {
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+    let page = Arc::new(Mutex::new(String::new()));
+    let shared = page.clone();
+    thread::Builder::new().name("metrics".to_string()).spawn(move || {
+      for stream in listener.incoming() {
+        match stream {
+          Ok(stream) => {
+            if let Err(e) = serve(stream, &shared) {
+              debug!("metrics request failed: {}", e);
+            }
+          },
+          Err(e) => warn!("metrics accept() failed: {}", e),
+        }
+      }
+    })?;
+    Ok(MetricsExporter { page, local_addr, last_publish: None })
+  }
+
+  pub fn local_addr(&self) -> SocketAddr {
+    self.local_addr
+  }
+
+  pub fn publish(&mut self, page: String) {
+    self.last_publish = Some(Instant::now());
+    *self.page.lock().unwrap() = page;
+  }
+
+  /// Render and publish the page unless it was published less than 100ms ago
+  pub fn publish_with<F: FnOnce() -> String>(&mut self, render: F) {
+    match self.last_publish {
+      Some(last) if last.elapsed() < PUBLISH_INTERVAL => {},
+      _ => self.publish(render()),
+    }
+  }
+}
+
+/// Bytes in the send queue of a TCP socket, written but not acknowledged
+/// by the peer yet (`SIOCOUTQ`)
+pub fn send_queue_bytes(fd: RawFd) -> Option<usize> {
+  let mut bytes: libc::c_int = 0;
+  match unsafe { libc::ioctl(fd, libc::TIOCOUTQ, &mut bytes) } {
+    0 => Some(bytes as usize),
+    _ => None,
+  }
+}
+
+fn serve(mut stream: TcpStream, page: &Mutex<String>) -> io::Result<()> {
+  stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+  let mut request = Vec::new();
+  let mut buf = [0; 1024];
+  while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 8192 {
+    let len = stream.read(&mut buf)?;
+    if len == 0 {
+      break;
+    }
+    request.extend_from_slice(&buf[..len]);
+  }
+  let request = String::from_utf8_lossy(&request);
+  let mut words = request.split_whitespace();
+  let response = match (words.next(), words.next()) {
+    (Some("GET"), Some(path)) if path == "/metrics" || path.starts_with("/metrics?") => {
+      let body = page.lock().unwrap().clone();
+      format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+    },
+    _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+  };
+  stream.write_all(response.as_bytes())
+}
+
+/// Builder of a page in the Prometheus text format
+#[derive(Default)]
+pub struct MetricsPage {
+  page: String,
+}
+
+impl MetricsPage {
+  pub fn new() -> Self {
+    MetricsPage::default()
+  }
+
+  /// Start a metric family, `kind` is `counter`, `gauge` or `summary`
+  pub fn family(&mut self, name: &str, kind: &str, help: &str) -> &mut Self {
+    let _ = writeln!(self.page, "# HELP {} {}", name, help);
+    let _ = writeln!(self.page, "# TYPE {} {}", name, kind);
+    self
+  }
+
+  pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) -> &mut Self {
+    self.page += name;
+    if !labels.is_empty() {
+      let labels: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect();
+      let _ = write!(self.page, "{{{}}}", labels.join(","));
+    }
+    let _ = writeln!(self.page, " {}", value);
+    self
+  }
+
+  /// A family with a single unlabeled sample
+  pub fn single(&mut self, name: &str, kind: &str, help: &str, value: f64) -> &mut Self {
+    self.family(name, kind, help).sample(name, &[], value)
+  }
+
+  pub fn render(&self) -> String {
+    self.page.clone()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn page() {
+    let mut page = MetricsPage::new();
+    page.single("dtp_blocks_total", "counter", "Blocks.", 3.0)
+      .family("dtp_bct_seconds", "summary", "BCT.")
+      .sample("dtp_bct_seconds", &[("class", "priority=1"), ("quantile", "0.5")], 0.25);
+    assert_eq!("# HELP dtp_blocks_total Blocks.\n# TYPE dtp_blocks_total counter\ndtp_blocks_total 3\n\
+      # HELP dtp_bct_seconds BCT.\n# TYPE dtp_bct_seconds summary\n\
+      dtp_bct_seconds{class=\"priority=1\",quantile=\"0.5\"} 0.25\n", page.render());
+  }
+
+  fn get(addr: SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+  }
+
+  #[test]
+  fn serve_metrics() {
+    let mut exporter = MetricsExporter::bind("127.0.0.1:0").unwrap();
+    exporter.publish("a 1\n".to_string());
+    // rendered again only after the interval
+    exporter.publish_with(|| "a 2\n".to_string());
+    let response = get(exporter.local_addr(), "/metrics");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\na 1\n"));
+    assert!(get(exporter.local_addr(), "/").starts_with("HTTP/1.1 404"));
+
+    let stream = TcpStream::connect(exporter.local_addr()).unwrap();
+    assert_eq!(Some(0), send_queue_bytes(std::os::unix::io::AsRawFd::as_raw_fd(&stream)));
+  }
+}
@@ -0,0 +1,157 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::results::create_output;
+
+/// LINKTYPE_USER0, the records hold raw TCP payload without any headers
+const LINKTYPE_USER0: u16 = 147;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const OPT_ENDOFOPT: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const IF_NAME: u16 = 2;
+const EPB_FLAGS: u16 = 2;
+
+/// Direction of a record, stored in the `epb_flags` option
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Direction {
+  Inbound,
+  Outbound,
+}
+
+/// A minimal pcapng writer
+///
+/// Every record is one `read()` or `write()` on the TCP stream. Timestamps are
+/// in microseconds, the default resolution of pcapng.
+pub struct PcapWriter<W: Write> {
+  out: W,
+  snaplen: usize,
+}
+
+impl PcapWriter<BufWriter<File>> {
+  /// Create the file at `path` and its parent directories
+  pub fn create(path: &Path, interface: &str, snaplen: usize) -> io::Result<Self> {
+    PcapWriter::new(BufWriter::new(create_output(path)?), interface, snaplen)
+  }
+}
+
+impl<W: Write> PcapWriter<W> {
+  /// Write the section header and one interface description.
+  /// At most `snaplen` bytes of every record are kept, 0 means all of them.
+  pub fn new(out: W, interface: &str, snaplen: usize) -> io::Result<Self> {
+    let mut writer = PcapWriter { out, snaplen };
+
+    let mut body = Vec::new();
+    body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+    body.extend_from_slice(&1u16.to_le_bytes()); // major version
+    body.extend_from_slice(&0u16.to_le_bytes()); // minor version
+    body.extend_from_slice(&(-1i64).to_le_bytes()); // section length unknown
+    push_option(&mut body, OPT_ENDOFOPT, &[]);
+    writer.write_block(SECTION_HEADER_BLOCK, &body)?;
+
+    let mut body = Vec::new();
+    body.extend_from_slice(&LINKTYPE_USER0.to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes()); // reserved
+    body.extend_from_slice(&(snaplen as u32).to_le_bytes());
+    push_option(&mut body, IF_NAME, interface.as_bytes());
+    push_option(&mut body, OPT_ENDOFOPT, &[]);
+    writer.write_block(INTERFACE_DESCRIPTION_BLOCK, &body)?;
+    Ok(writer)
+  }
+
+  /// Write one record of `data` seen at `timestamp` (us), every comment
+  /// becomes an `opt_comment` option
+  pub fn write_packet(&mut self, timestamp: u64, direction: Direction, data: &[u8], comments: &[String]) -> io::Result<()> {
+    let captured = if self.snaplen == 0 { data.len() } else { data.len().min(self.snaplen) };
+    let mut body = Vec::with_capacity(captured + 64);
+    body.extend_from_slice(&0u32.to_le_bytes()); // interface id
+    body.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+    body.extend_from_slice(&(timestamp as u32).to_le_bytes());
+    body.extend_from_slice(&(captured as u32).to_le_bytes());
+    body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    body.extend_from_slice(&data[..captured]);
+    pad(&mut body);
+    for comment in comments {
+      push_option(&mut body, OPT_COMMENT, comment.as_bytes());
+    }
+    let flags: u32 = match direction {
+      Direction::Inbound => 1,
+      Direction::Outbound => 2,
+    };
+    push_option(&mut body, EPB_FLAGS, &flags.to_le_bytes());
+    push_option(&mut body, OPT_ENDOFOPT, &[]);
+    self.write_block(ENHANCED_PACKET_BLOCK, &body)
+  }
+
+  pub fn flush(&mut self) -> io::Result<()> {
+    self.out.flush()
+  }
+
+  fn write_block(&mut self, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let total_len = (body.len() + 12) as u32;
+    self.out.write_all(&block_type.to_le_bytes())?;
+    self.out.write_all(&total_len.to_le_bytes())?;
+    self.out.write_all(body)?;
+    self.out.write_all(&total_len.to_le_bytes())
+  }
+}
+
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+  body.extend_from_slice(&code.to_le_bytes());
+  body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+  body.extend_from_slice(value);
+  pad(body);
+}
+
+// pad to 32 bits
+fn pad(body: &mut Vec<u8>) {
+  while body.len() & 3 != 0 {
+    body.push(0);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&buf[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+  }
+
+  #[test]
+  fn blocks() {
+    let mut out = Vec::new();
+    {
+      let mut writer = PcapWriter::new(&mut out, "tcp_client", 4).unwrap();
+      writer.write_packet(0x1_0000_0002, Direction::Inbound, &[1, 2, 3, 4, 5], &["bytes 0-5".to_string()]).unwrap();
+    }
+    // walk the blocks by their lengths
+    let mut offset = 0;
+    let mut types = vec![];
+    while offset < out.len() {
+      let len = u32_at(&out, offset + 4) as usize;
+      assert_eq!(0, len % 4);
+      assert_eq!(len as u32, u32_at(&out, offset + len - 4));
+      types.push(u32_at(&out, offset));
+      offset += len;
+    }
+    assert_eq!(out.len(), offset);
+    assert_eq!(vec![SECTION_HEADER_BLOCK, INTERFACE_DESCRIPTION_BLOCK, ENHANCED_PACKET_BLOCK], types);
+
+    let epb = &out[out.len() - u32_at(&out, out.len() - 4) as usize..];
+    assert_eq!(1, u32_at(epb, 12)); // timestamp high
+    assert_eq!(2, u32_at(epb, 16)); // timestamp low
+    assert_eq!(4, u32_at(epb, 20)); // captured
+    assert_eq!(5, u32_at(epb, 24)); // original
+    assert_eq!(&[1, 2, 3, 4], &epb[28..32]);
+    assert_eq!(OPT_COMMENT as u32 | (9 << 16), u32_at(epb, 32));
+    assert_eq!(b"bytes 0-5", &epb[36..45]);
+  }
+}
@@ -0,0 +1,98 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+/// Description of a run, the `run` object of a results JSON document
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RunMetadata {
+  pub program: String,
+  pub version: String,
+  pub dtp_utils_version: String,
+  pub command_line: Vec<String>,
+  pub local_addr: Option<String>,
+  pub peer_addr: Option<String>,
+  /// congestion control algorithm reported by the socket
+  pub cc_algorithm: Option<String>,
+  pub trace: Option<String>,
+  /// sha256 of the trace file in hex
+  pub trace_sha256: Option<String>,
+  /// unix time in us
+  pub start_time: u64,
+  pub end_time: u64,
+  /// why the run stopped early, a signal or the abort message of the peer,
+  /// `None` if it completed
+  pub interrupted: Option<String>,
+}
+
+impl RunMetadata {
+  /// `version` is the `CARGO_PKG_VERSION` of the binary
+  pub fn new(program: &str, version: &str) -> Self {
+    RunMetadata {
+      program: program.to_string(),
+      version: version.to_string(),
+      dtp_utils_version: env!("CARGO_PKG_VERSION").to_string(),
+      command_line: std::env::args().collect(),
+      start_time: crate::get_current_usec(),
+      ..RunMetadata::default()
+    }
+  }
+
+  pub fn set_trace(&mut self, path: &str) -> io::Result<()> {
+    self.trace_sha256 = Some(sha256_hex(&fs::read(path)?));
+    self.trace = Some(path.to_string());
+    Ok(())
+  }
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+  let digest = ring::digest::digest(&ring::digest::SHA256, data);
+  digest.as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Path of a result file, relative paths are under `dir`
+pub fn output_path(dir: &str, path: &str) -> PathBuf {
+  Path::new(dir).join(path)
+}
+
+/// Create the parent directories of `path` and the file itself
+pub fn create_output(path: &Path) -> io::Result<File> {
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  File::create(path)
+}
+
+pub fn write_json<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+  let mut out = BufWriter::new(create_output(path)?);
+  serde_json::to_writer_pretty(&mut out, value)?;
+  out.write_all(b"\n")?;
+  out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn sha256() {
+    assert_eq!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad", sha256_hex(b"abc"));
+  }
+
+  #[test]
+  fn output() {
+    assert_eq!(Path::new("out/log/a.log"), output_path("out", "log/a.log"));
+    assert_eq!(Path::new("/tmp/a.json"), output_path("out", "/tmp/a.json"));
+
+    let dir = std::env::temp_dir().join(format!("results_test_{}", std::process::id()));
+    let path = dir.join("nested/results.json");
+    let mut run = RunMetadata::new("test", "0.1.0");
+    run.cc_algorithm = Some("reno".to_string());
+    write_json(&path, &serde_json::json!({ "run": run })).unwrap();
+    let value: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+    assert_eq!("reno", value["run"]["cc_algorithm"]);
+    assert!(value["run"]["trace"].is_null());
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
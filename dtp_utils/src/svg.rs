@@ -0,0 +1,172 @@
+use std::fmt::Write;
+
+/// Colors of the series, in order
+const COLORS: [&str; 6] = ["#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b"];
+
+const WIDTH: f64 = 800.0;
+const HEIGHT: f64 = 400.0;
+const LEFT: f64 = 80.0;
+const RIGHT: f64 = 20.0;
+const TOP: f64 = 40.0;
+const BOTTOM: f64 = 50.0;
+const TICKS: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Style {
+  /// points joined by straight lines
+  Line,
+  /// a horizontal step from every point to the next
+  Steps,
+  /// a dot per point
+  Points,
+}
+
+#[derive(Clone, Debug)]
+pub struct Series {
+  pub name: String,
+  pub style: Style,
+  pub points: Vec<(f64, f64)>,
+}
+
+/// A minimal x/y chart rendered as a standalone SVG document
+#[derive(Clone, Debug, Default)]
+pub struct Plot {
+  pub title: String,
+  pub x_label: String,
+  pub y_label: String,
+  pub series: Vec<Series>,
+}
+
+impl Plot {
+  pub fn new(title: &str, x_label: &str, y_label: &str) -> Self {
+    Plot {
+      title: title.to_string(),
+      x_label: x_label.to_string(),
+      y_label: y_label.to_string(),
+      series: Vec::new(),
+    }
+  }
+
+  pub fn series(&mut self, name: &str, style: Style, points: Vec<(f64, f64)>) -> &mut Self {
+    self.series.push(Series { name: name.to_string(), style, points });
+    self
+  }
+
+  // data bounds, y always starts at 0
+  fn bounds(&self) -> (f64, f64, f64) {
+    let points = self.series.iter().flat_map(|s| s.points.iter()).filter(|(x, y)| x.is_finite() && y.is_finite());
+    let (mut x_min, mut x_max, mut y_max) = (f64::INFINITY, f64::NEG_INFINITY, 0.0f64);
+    for (x, y) in points {
+      x_min = x_min.min(*x);
+      x_max = x_max.max(*x);
+      y_max = y_max.max(*y);
+    }
+    if x_min > x_max {
+      return (0.0, 1.0, 1.0);
+    }
+    if x_max <= x_min {
+      x_max = x_min + 1.0;
+    }
+    if y_max <= 0.0 {
+      y_max = 1.0;
+    }
+    (x_min, x_max, y_max * 1.05)
+  }
+
+  pub fn to_svg(&self) -> String {
+    let (x_min, x_max, y_max) = self.bounds();
+    let (w, h) = (WIDTH - LEFT - RIGHT, HEIGHT - TOP - BOTTOM);
+    let sx = |x: f64| LEFT + (x - x_min) / (x_max - x_min) * w;
+    let sy = |y: f64| TOP + h - y / y_max * h;
+
+    let mut s = String::new();
+    let _ = writeln!(s, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="sans-serif" font-size="12">"#, WIDTH, HEIGHT);
+    let _ = writeln!(s, r#"<rect width="100%" height="100%" fill="white"/>"#);
+    let _ = writeln!(s, r#"<text x="{}" y="20" text-anchor="middle" font-size="14">{}</text>"#, WIDTH / 2.0, escape(&self.title));
+    // axes and ticks
+    let _ = writeln!(s, r#"<path d="M{:.1},{:.1} V{:.1} H{:.1}" stroke="black" fill="none"/>"#, LEFT, TOP, TOP + h, LEFT + w);
+    for i in 0..=TICKS {
+      let x = x_min + (x_max - x_min) * i as f64 / TICKS as f64;
+      let y = y_max * i as f64 / TICKS as f64;
+      let _ = writeln!(s, r#"<text x="{:.1}" y="{:.1}" text-anchor="middle">{}</text>"#, sx(x), TOP + h + 16.0, number(x));
+      let _ = writeln!(s, r##"<path d="M{:.1},{:.1} H{:.1}" stroke="#ddd"/>"##, LEFT, sy(y), LEFT + w);
+      let _ = writeln!(s, r#"<text x="{:.1}" y="{:.1}" text-anchor="end">{}</text>"#, LEFT - 4.0, sy(y) + 4.0, number(y));
+    }
+    let _ = writeln!(s, r#"<text x="{:.1}" y="{:.1}" text-anchor="middle">{}</text>"#, LEFT + w / 2.0, HEIGHT - 10.0, escape(&self.x_label));
+    let _ = writeln!(s, r#"<text transform="translate(16,{:.1}) rotate(-90)" text-anchor="middle">{}</text>"#, TOP + h / 2.0, escape(&self.y_label));
+
+    for (i, series) in self.series.iter().enumerate() {
+      let color = COLORS[i % COLORS.len()];
+      let points = series.points.iter().filter(|(x, y)| x.is_finite() && y.is_finite());
+      match series.style {
+        Style::Points => {
+          for (x, y) in points {
+            let _ = writeln!(s, r#"<circle cx="{:.1}" cy="{:.1}" r="1.5" fill="{}"/>"#, sx(*x), sy(*y), color);
+          }
+        },
+        Style::Line | Style::Steps => {
+          let mut d = String::new();
+          let mut last_y = None;
+          for (x, y) in points {
+            match last_y {
+              None => { let _ = write!(d, "M{:.1},{:.1}", sx(*x), sy(*y)); },
+              Some(last) if series.style == Style::Steps => { let _ = write!(d, " L{:.1},{:.1} L{:.1},{:.1}", sx(*x), sy(last), sx(*x), sy(*y)); },
+              Some(_) => { let _ = write!(d, " L{:.1},{:.1}", sx(*x), sy(*y)); },
+            }
+            last_y = Some(*y);
+          }
+          let _ = writeln!(s, r#"<path d="{}" stroke="{}" fill="none"/>"#, d, color);
+        },
+      }
+      // legend
+      let y = TOP + 14.0 * i as f64;
+      let _ = writeln!(s, r#"<rect x="{:.1}" y="{:.1}" width="10" height="10" fill="{}"/>"#, LEFT + w - 150.0, y, color);
+      let _ = writeln!(s, r#"<text x="{:.1}" y="{:.1}">{}</text>"#, LEFT + w - 135.0, y + 9.0, escape(&series.name));
+    }
+    s += "</svg>\n";
+    s
+  }
+}
+
+fn escape(s: &str) -> String {
+  s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+// tick labels like 1.5, 20k or 3.2M
+fn number(v: f64) -> String {
+  let (v, suffix) = match v.abs() {
+    a if a >= 1e9 => (v / 1e9, "G"),
+    a if a >= 1e6 => (v / 1e6, "M"),
+    a if a >= 1e4 => (v / 1e3, "k"),
+    _ => (v, ""),
+  };
+  let s = format!("{:.2}", v);
+  let s = s.trim_end_matches('0').trim_end_matches('.');
+  format!("{}{}", s, suffix)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn svg() {
+    let mut plot = Plot::new("load <bits>", "time (s)", "bits/s");
+    plot.series("all", Style::Steps, vec![(0.0, 1e6), (1.0, 2e6), (2.0, 0.0)])
+      .series("p1", Style::Points, vec![(0.5, 3.0), (f64::NAN, 1.0)]);
+    let svg = plot.to_svg();
+    assert!(svg.starts_with("<svg "));
+    assert!(svg.ends_with("</svg>\n"));
+    assert!(svg.contains("load &lt;bits&gt;"));
+    assert_eq!(1, svg.matches("<circle").count());
+    assert_eq!(svg.matches('<').count(), svg.matches('>').count());
+  }
+
+  #[test]
+  fn labels() {
+    assert_eq!("0", number(0.0));
+    assert_eq!("1.5", number(1.5));
+    assert_eq!("20k", number(20000.0));
+    assert_eq!("3.25M", number(3_250_000.0));
+  }
+}
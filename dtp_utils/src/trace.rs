@@ -0,0 +1,104 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::dtp_config;
+
+/// Most blocks `get_dtp_config` reads from a file, the rest are ignored
+pub const MAX_TRACE_LEN: usize = 10000;
+
+/// Check what the server expects of a trace: non-negative gaps, positive
+/// deadlines and block sizes, non-negative priorities, and no more blocks
+/// than `get_dtp_config` reads
+pub fn validate(cfgs: &[dtp_config]) -> Result<(), String> {
+  if cfgs.len() > MAX_TRACE_LEN {
+    return Err(format!("{} blocks, at most {} are read", cfgs.len(), MAX_TRACE_LEN));
+  }
+  for (i, cfg) in cfgs.iter().enumerate() {
+    if !cfg.send_time_gap.is_finite() || cfg.send_time_gap < 0.0 {
+      return Err(format!("block {}: invalid send_time_gap {}", i, cfg.send_time_gap));
+    }
+    if cfg.deadline <= 0 {
+      return Err(format!("block {}: invalid deadline {}", i, cfg.deadline));
+    }
+    if cfg.block_size == 0 {
+      return Err(format!("block {}: invalid block_size {}", i, cfg.block_size));
+    }
+    if cfg.priority < 0 {
+      return Err(format!("block {}: invalid priority {}", i, cfg.priority));
+    }
+  }
+  Ok(())
+}
+
+/// Write `cfgs` in the format read by `get_dtp_config`, one block per line:
+/// `send_time_gap deadline block_size priority`
+pub fn write_dtp_config<W: Write>(out: &mut W, cfgs: &[dtp_config]) -> io::Result<()> {
+  for cfg in cfgs {
+    writeln!(out, "{}    {}    {}    {}", cfg.send_time_gap, cfg.deadline, cfg.block_size, cfg.priority)?;
+  }
+  Ok(())
+}
+
+pub fn save_dtp_config(path: &str, cfgs: &[dtp_config]) -> io::Result<()> {
+  let mut out = BufWriter::new(File::create(path)?);
+  write_dtp_config(&mut out, cfgs)?;
+  out.flush()
+}
+
+/// Send times of the blocks in seconds since the start
+pub fn send_times(cfgs: &[dtp_config]) -> Vec<f64> {
+  let mut t = 0.0;
+  cfgs
+    .iter()
+    .map(|cfg| {
+      t += cfg.send_time_gap as f64;
+      t
+    })
+    .collect()
+}
+
+/// Blocks sent at the given times in seconds, which must be ascending
+pub fn from_send_times<I: IntoIterator<Item = (f64, i32, u64, i32)>>(blocks: I) -> Vec<dtp_config> {
+  let mut last = 0.0;
+  blocks
+    .into_iter()
+    .map(|(t, deadline, block_size, priority)| {
+      let gap = (t - last).max(0.0);
+      last = t;
+      dtp_config { send_time_gap: gap as f32, deadline, block_size, priority }
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn roundtrip() {
+    let cfgs = from_send_times(vec![(0.5, 200, 1000, 1), (0.75, 100, 50000, 2), (0.75, 100, 10, 1), (1.0, 100, 5_000_000_000, 1)]);
+    assert_eq!(0.25, cfgs[1].send_time_gap);
+    assert_eq!(0.0, cfgs[2].send_time_gap);
+    assert_eq!(Ok(()), validate(&cfgs));
+    assert_eq!(vec![0.5, 0.75, 0.75, 1.0], send_times(&cfgs));
+
+    let path = std::env::temp_dir().join(format!("trace_test_{}.txt", std::process::id()));
+    let path = path.to_str().unwrap();
+    save_dtp_config(path, &cfgs).unwrap();
+    let read = crate::get_dtp_config(path);
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(cfgs.len(), read.len());
+    for (a, b) in cfgs.iter().zip(read.iter()) {
+      assert_eq!((a.send_time_gap, a.deadline, a.block_size, a.priority), (b.send_time_gap, b.deadline, b.block_size, b.priority));
+    }
+  }
+
+  #[test]
+  fn invalid() {
+    let cfg = dtp_config { send_time_gap: 0.1, deadline: 200, block_size: 100, priority: 1 };
+    assert!(validate(&[dtp_config { block_size: 0, ..cfg }]).is_err());
+    assert!(validate(&[dtp_config { deadline: -1, ..cfg }]).is_err());
+    assert!(validate(&[dtp_config { send_time_gap: f32::NAN, ..cfg }]).is_err());
+    assert!(validate(&vec![cfg; MAX_TRACE_LEN + 1]).is_err());
+  }
+}
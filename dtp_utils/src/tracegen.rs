@@ -0,0 +1,269 @@
+use crate::dtp_config;
+use crate::trace::{from_send_times, MAX_TRACE_LEN};
+
+/// Workload models of `generate`, sizes are in bytes and deadlines in ms
+#[derive(Clone, Debug, PartialEq)]
+pub enum Model {
+  /// A frame every `1 / fps` s, the first of every `gop` frames is an I frame
+  Video {
+    fps: f64,
+    gop: u32,
+    i_size: u64,
+    p_size: u64,
+    i_priority: i32,
+    p_priority: i32,
+    deadline: i32,
+  },
+  /// Blocks with exponential inter-arrival times of mean `1 / rate` s
+  Poisson { rate: f64, size: u64, priority: i32, deadline: i32 },
+  /// Poisson arrivals at `rate` during on periods, nothing during off
+  /// periods, both of exponential length with means `on` and `off` s
+  OnOff { rate: f64, on: f64, off: f64, size: u64, priority: i32, deadline: i32 },
+  /// Game state: an update every `1 / tick` s, every `snapshot` ticks a
+  /// full snapshot instead
+  Game {
+    tick: f64,
+    update_size: u64,
+    snapshot: u32,
+    snapshot_size: u64,
+    update_priority: i32,
+    snapshot_priority: i32,
+    deadline: i32,
+  },
+  /// A report every `1 / rate` s
+  Telemetry { rate: f64, size: u64, priority: i32, deadline: i32 },
+}
+
+/// xorshift64*, a fixed generator so that a seed gives the same trace on
+/// every platform and version
+#[derive(Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+  pub fn new(seed: u64) -> Self {
+    // splitmix64 of the seed, xorshift must not start from 0
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    Rng((z ^ (z >> 31)) | 1)
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.0 ^= self.0 >> 12;
+    self.0 ^= self.0 << 25;
+    self.0 ^= self.0 >> 27;
+    self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+  }
+
+  /// Uniform in [0, 1)
+  pub fn uniform(&mut self) -> f64 {
+    (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+  }
+
+  /// Exponential of the given mean
+  pub fn exp(&mut self, mean: f64) -> f64 {
+    -mean * (1.0 - self.uniform()).ln()
+  }
+
+  /// `size` scaled by a uniform factor in [1 - jitter, 1 + jitter], at least 1
+  pub fn jitter(&mut self, size: u64, jitter: f64) -> u64 {
+    let factor = 1.0 + jitter * (2.0 * self.uniform() - 1.0);
+    ((size as f64 * factor).round() as u64).max(1)
+  }
+}
+
+// (send time, deadline, block size, priority)
+type Block = (f64, i32, u64, i32);
+
+// a positive finite number, the rates and lengths of the models
+fn positive(name: &str, value: f64) -> Result<(), String> {
+  if value.is_finite() && value > 0.0 {
+    Ok(())
+  } else {
+    Err(format!("invalid {} {}, expect a positive number", name, value))
+  }
+}
+
+// one block more than a trace may have is enough to reject it
+fn full(blocks: &[Block]) -> bool {
+  blocks.len() > MAX_TRACE_LEN
+}
+
+fn periodic<F: FnMut(u64) -> (u64, i32)>(rate: f64, duration: f64, mut block: F, deadline: i32) -> Vec<Block> {
+  let mut ret = Vec::new();
+  let mut k = 0;
+  loop {
+    let t = k as f64 / rate;
+    if t >= duration || full(&ret) {
+      return ret;
+    }
+    let (size, priority) = block(k);
+    ret.push((t, deadline, size, priority));
+    k += 1;
+  }
+}
+
+impl Model {
+  /// Whether the rates and lengths of the model are positive and finite,
+  /// so that `blocks` ends
+  pub fn check(&self) -> Result<(), String> {
+    match *self {
+      Model::Video { fps, .. } => positive("fps", fps),
+      Model::Poisson { rate, .. } | Model::Telemetry { rate, .. } => positive("rate", rate),
+      Model::OnOff { rate, on, off, .. } => {
+        positive("rate", rate)?;
+        positive("on period", on)?;
+        positive("off period", off)
+      },
+      Model::Game { tick, .. } => positive("tick", tick),
+    }
+  }
+
+  /// Blocks of this model in `[0, duration)` s, at most one more than
+  /// `MAX_TRACE_LEN`
+  fn blocks(&self, duration: f64, jitter: f64, rng: &mut Rng) -> Vec<Block> {
+    match *self {
+      Model::Video { fps, gop, i_size, p_size, i_priority, p_priority, deadline } => {
+        let gop = gop.max(1) as u64;
+        periodic(fps, duration, |k| {
+          if k % gop == 0 {
+            (rng.jitter(i_size, jitter), i_priority)
+          } else {
+            (rng.jitter(p_size, jitter), p_priority)
+          }
+        }, deadline)
+      },
+      Model::Poisson { rate, size, priority, deadline } => {
+        let mut ret = Vec::new();
+        let mut t = rng.exp(1.0 / rate);
+        while t < duration && !full(&ret) {
+          ret.push((t, deadline, rng.jitter(size, jitter), priority));
+          t += rng.exp(1.0 / rate);
+        }
+        ret
+      },
+      Model::OnOff { rate, on, off, size, priority, deadline } => {
+        let mut ret = Vec::new();
+        let mut start = 0.0;
+        while start < duration && !full(&ret) {
+          let end = (start + rng.exp(on)).min(duration);
+          let mut t = start + rng.exp(1.0 / rate);
+          while t < end && !full(&ret) {
+            ret.push((t, deadline, rng.jitter(size, jitter), priority));
+            t += rng.exp(1.0 / rate);
+          }
+          start = end + rng.exp(off);
+        }
+        ret
+      },
+      Model::Game { tick, update_size, snapshot, snapshot_size, update_priority, snapshot_priority, deadline } => {
+        let snapshot = snapshot.max(1) as u64;
+        periodic(tick, duration, |k| {
+          if k % snapshot == 0 {
+            (rng.jitter(snapshot_size, jitter), snapshot_priority)
+          } else {
+            (rng.jitter(update_size, jitter), update_priority)
+          }
+        }, deadline)
+      },
+      Model::Telemetry { rate, size, priority, deadline } => {
+        periodic(rate, duration, |_| (rng.jitter(size, jitter), priority), deadline)
+      },
+    }
+  }
+}
+
+/// A trace of `duration` s with the blocks of all the models merged by send
+/// time, block sizes vary by up to `jitter` (a fraction) around the sizes of
+/// the models
+///
+/// Every model draws from its own generator seeded by `seed` and its index,
+/// so adding a model leaves the blocks of the others unchanged. A trace of
+/// more than `MAX_TRACE_LEN` blocks is an error, found before it is
+/// generated completely.
+pub fn generate(models: &[Model], duration: f64, jitter: f64, seed: u64) -> Result<Vec<dtp_config>, String> {
+  positive("duration", duration)?;
+  for model in models {
+    model.check()?;
+  }
+  let mut blocks: Vec<Block> = Vec::new();
+  for (i, model) in models.iter().enumerate() {
+    let mut rng = Rng::new(seed.wrapping_add((i as u64) << 32));
+    blocks.extend(model.blocks(duration, jitter, &mut rng));
+    if full(&blocks) {
+      return Err(format!("more than {} blocks, shorten the duration or lower the rates", MAX_TRACE_LEN));
+    }
+  }
+  // stable, the blocks of the same time stay in the order of the models
+  blocks.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+  Ok(from_send_times(blocks))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::trace::{send_times, validate};
+
+  fn video() -> Model {
+    Model::Video { fps: 30.0, gop: 10, i_size: 50000, p_size: 5000, i_priority: 2, p_priority: 1, deadline: 200 }
+  }
+
+  #[test]
+  fn video_gop() {
+    let cfgs = generate(&[video()], 2.0, 0.0, 1).unwrap();
+    assert_eq!(60, cfgs.len());
+    assert_eq!(Ok(()), validate(&cfgs));
+    for (k, cfg) in cfgs.iter().enumerate() {
+      let (size, priority) = if k % 10 == 0 { (50000, 2) } else { (5000, 1) };
+      assert_eq!((size, priority), (cfg.block_size, cfg.priority));
+    }
+    assert!((cfgs[1].send_time_gap - 1.0 / 30.0).abs() < 1e-6);
+  }
+
+  #[test]
+  fn seeds() {
+    let models = [Model::Poisson { rate: 100.0, size: 1000, priority: 1, deadline: 100 }];
+    let a = generate(&models, 10.0, 0.2, 7).unwrap();
+    let b = generate(&models, 10.0, 0.2, 7).unwrap();
+    let c = generate(&models, 10.0, 0.2, 8).unwrap();
+    let key = |cfgs: &[dtp_config]| cfgs.iter().map(|cfg| (cfg.send_time_gap, cfg.block_size)).collect::<Vec<_>>();
+    assert_eq!(key(&a), key(&b));
+    assert_ne!(key(&a), key(&c));
+    // about 1000 arrivals
+    assert!(a.len() > 850 && a.len() < 1150, "{}", a.len());
+    assert!(a.iter().all(|cfg| cfg.block_size >= 800 && cfg.block_size <= 1200));
+  }
+
+  #[test]
+  fn mix() {
+    let models = [
+      Model::Game { tick: 20.0, update_size: 200, snapshot: 20, snapshot_size: 20000, update_priority: 3, snapshot_priority: 2, deadline: 50 },
+      Model::Telemetry { rate: 1.0, size: 500, priority: 1, deadline: 1000 },
+      Model::OnOff { rate: 50.0, on: 0.5, off: 0.5, size: 3000, priority: 1, deadline: 300 },
+    ];
+    let cfgs = generate(&models, 5.0, 0.1, 1).unwrap();
+    assert_eq!(Ok(()), validate(&cfgs));
+    let times = send_times(&cfgs);
+    assert!(times.windows(2).all(|w| w[0] <= w[1]));
+    assert!(*times.last().unwrap() < 5.0 + 1e-3);
+    assert_eq!(5, cfgs.iter().filter(|cfg| cfg.priority == 2).count());
+    assert_eq!(5, cfgs.iter().filter(|cfg| cfg.deadline == 1000).count());
+    // the game blocks do not depend on the other models
+    let game = generate(&models[..1], 5.0, 0.1, 1).unwrap();
+    let sizes = |cfgs: &[dtp_config]| cfgs.iter().filter(|cfg| cfg.deadline == 50).map(|cfg| cfg.block_size).collect::<Vec<_>>();
+    assert_eq!(sizes(&game), sizes(&cfgs));
+  }
+
+  #[test]
+  fn degenerate() {
+    let onoff = |on, off| Model::OnOff { rate: 10.0, on, off, size: 1000, priority: 1, deadline: 100 };
+    assert!(generate(&[onoff(0.0, 0.0)], 10.0, 0.0, 1).is_err());
+    assert!(generate(&[onoff(1.0, -1.0)], 10.0, 0.0, 1).is_err());
+    let video = Model::Video { fps: f64::INFINITY, gop: 10, i_size: 50000, p_size: 5000, i_priority: 2, p_priority: 1, deadline: 200 };
+    assert!(generate(&[video], 10.0, 0.0, 1).is_err());
+    assert!(generate(&[onoff(1.0, 1.0)], f64::NAN, 0.0, 1).is_err());
+    // stops long before the end
+    let telemetry = Model::Telemetry { rate: 1000.0, size: 100, priority: 1, deadline: 100 };
+    assert!(generate(&[telemetry], 1e12, 0.0, 1).is_err());
+  }
+}
@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use serde::Serialize;
+
+use crate::dtp_config;
+use crate::svg::{Plot, Style};
+use crate::trace::send_times;
+
+/// Quantiles of a set of values, nearest rank
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Distribution {
+  pub count: usize,
+  pub min: f64,
+  pub mean: f64,
+  pub p50: f64,
+  pub p90: f64,
+  pub p99: f64,
+  pub max: f64,
+}
+
+impl Distribution {
+  pub fn of(values: &[f64]) -> Self {
+    if values.is_empty() {
+      return Distribution::default();
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let quantile = |q: f64| sorted[((q * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len()) - 1];
+    Distribution {
+      count: sorted.len(),
+      min: sorted[0],
+      mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+      p50: quantile(0.5),
+      p90: quantile(0.9),
+      p99: quantile(0.99),
+      max: sorted[sorted.len() - 1],
+    }
+  }
+}
+
+/// What a trace demands of the link
+#[derive(Clone, Debug, Serialize)]
+pub struct TraceStats {
+  pub blocks: usize,
+  pub bytes: u64,
+  /// send time of the last block in s
+  pub duration: f64,
+  /// latest deadline of any block in s since the start
+  pub last_deadline: f64,
+  /// width of the load windows in s
+  pub window: f64,
+  /// offered load of every window in bits/s, by send time
+  pub load: Vec<f64>,
+  pub peak_load: f64,
+  /// bits over the duration, or over one window if all blocks are sent at once
+  pub average_load: f64,
+  /// block sizes in bytes by priority
+  pub sizes: BTreeMap<i32, Distribution>,
+  /// deadlines in ms
+  pub deadlines: Distribution,
+  /// number of blocks of every deadline in ms
+  pub deadline_counts: BTreeMap<i32, usize>,
+  /// per block, see `required_rates`
+  pub required_rates: Vec<f64>,
+  pub required: Distribution,
+  /// the lowest rate at which every block meets its deadline
+  pub min_bandwidth: f64,
+}
+
+/// The lowest constant rate in bits/s at which each block is delivered
+/// before its deadline, with the blocks sent in order through a FIFO link
+/// and the blocks before it still queued
+///
+/// At rate `r` block `i` is done at `max_j (t_j + S(j, i) / r)` over the
+/// blocks `j <= i`, where `S(j, i)` is the size of blocks `j..=i`, so it
+/// needs `r >= S(j, i) / (t_i + d_i - t_j)` for every such `j`.
+pub fn required_rates(cfgs: &[dtp_config]) -> Vec<f64> {
+  let times = send_times(cfgs);
+  let mut rates = Vec::with_capacity(cfgs.len());
+  for i in 0..cfgs.len() {
+    let due = times[i] + cfgs[i].deadline as f64 / 1000.0;
+    let mut bits = 0.0;
+    let mut rate = 0.0f64;
+    for j in (0..=i).rev() {
+      bits += cfgs[j].block_size as f64 * 8.0;
+      rate = rate.max(bits / (due - times[j]));
+    }
+    rates.push(rate);
+  }
+  rates
+}
+
+impl TraceStats {
+  /// Statistics of a trace, the offered load is measured over windows of
+  /// `window` s
+  pub fn compute(cfgs: &[dtp_config], window: f64) -> Self {
+    let times = send_times(cfgs);
+    let duration = times.last().copied().unwrap_or(0.0);
+    let bytes = cfgs.iter().map(|cfg| cfg.block_size).sum::<u64>();
+
+    let mut load = vec![0.0; if cfgs.is_empty() { 0 } else { (duration / window) as usize + 1 }];
+    for (t, cfg) in times.iter().zip(cfgs) {
+      load[(t / window) as usize] += cfg.block_size as f64 * 8.0 / window;
+    }
+
+    let mut by_priority: BTreeMap<i32, Vec<f64>> = BTreeMap::new();
+    let mut deadline_counts = BTreeMap::new();
+    for cfg in cfgs {
+      by_priority.entry(cfg.priority).or_default().push(cfg.block_size as f64);
+      *deadline_counts.entry(cfg.deadline).or_insert(0) += 1;
+    }
+    let deadlines: Vec<f64> = cfgs.iter().map(|cfg| cfg.deadline as f64).collect();
+    let required_rates = required_rates(cfgs);
+
+    TraceStats {
+      blocks: cfgs.len(),
+      bytes,
+      duration,
+      last_deadline: times.iter().zip(cfgs).map(|(t, cfg)| t + cfg.deadline as f64 / 1000.0).fold(0.0, f64::max),
+      window,
+      peak_load: load.iter().copied().fold(0.0, f64::max),
+      average_load: bytes as f64 * 8.0 / if duration > 0.0 { duration } else { window },
+      load,
+      sizes: by_priority.iter().map(|(p, sizes)| (*p, Distribution::of(sizes))).collect(),
+      deadlines: Distribution::of(&deadlines),
+      deadline_counts,
+      required: Distribution::of(&required_rates),
+      min_bandwidth: required_rates.iter().copied().fold(0.0, f64::max),
+      required_rates,
+    }
+  }
+
+  /// Plain text report
+  pub fn report(&self) -> String {
+    let mut s = String::new();
+    let _ = writeln!(s, "blocks: {}, bytes: {}", self.blocks, self.bytes);
+    let _ = writeln!(s, "duration: {:.3}s, last deadline: {:.3}s", self.duration, self.last_deadline);
+    let _ = writeln!(s, "offered load ({}s windows): average {:.0} bits/s, peak {:.0} bits/s", self.window, self.average_load, self.peak_load);
+    let _ = writeln!(s, "\nblock sizes (bytes):");
+    let _ = writeln!(s, "{:>8} {:>8} {:>10} {:>10} {:>10} {:>10} {:>10} {:>12}", "priority", "blocks", "min", "mean", "p50", "p90", "max", "bytes");
+    for (priority, d) in &self.sizes {
+      let _ = writeln!(
+        s,
+        "{:>8} {:>8} {:>10.0} {:>10.0} {:>10.0} {:>10.0} {:>10.0} {:>12.0}",
+        priority, d.count, d.min, d.mean, d.p50, d.p90, d.max, d.mean * d.count as f64
+      );
+    }
+    let d = &self.deadlines;
+    let _ = writeln!(s, "\ndeadlines (ms): min {} p50 {} p90 {} max {}", d.min, d.p50, d.p90, d.max);
+    for (deadline, count) in &self.deadline_counts {
+      let _ = writeln!(s, "{:>8} {:>8}", deadline, count);
+    }
+    let d = &self.required;
+    let _ = writeln!(
+      s,
+      "\nrequired bandwidth (bits/s): p50 {:.0} p90 {:.0} p99 {:.0} max {:.0}",
+      d.p50, d.p90, d.p99, d.max
+    );
+    let _ = writeln!(s, "minimum bandwidth for every deadline: {:.3} Mbit/s", self.min_bandwidth / 1e6);
+    s
+  }
+
+  /// SVG plots by name: `load` (offered load per window), `sizes` (block
+  /// size by send time and priority), `deadlines` (deadline by send time)
+  /// and `required` (required bandwidth per block)
+  pub fn plots(&self, cfgs: &[dtp_config]) -> Vec<(&'static str, String)> {
+    let times = send_times(cfgs);
+    let mut load = Plot::new("Offered load", "time (s)", "bits/s");
+    let mut points: Vec<(f64, f64)> = self.load.iter().enumerate().map(|(i, l)| (i as f64 * self.window, *l)).collect();
+    points.push((self.load.len() as f64 * self.window, self.load.last().copied().unwrap_or(0.0)));
+    load
+      .series(&format!("{}s windows", self.window), Style::Steps, points)
+      .series("average", Style::Line, vec![(0.0, self.average_load), (self.load.len() as f64 * self.window, self.average_load)]);
+
+    let mut sizes = Plot::new("Block sizes", "send time (s)", "bytes");
+    for priority in self.sizes.keys() {
+      let points = times.iter().zip(cfgs).filter(|(_, cfg)| cfg.priority == *priority).map(|(t, cfg)| (*t, cfg.block_size as f64)).collect();
+      sizes.series(&format!("priority {}", priority), Style::Points, points);
+    }
+
+    let mut deadlines = Plot::new("Deadlines", "send time (s)", "ms");
+    deadlines.series("deadline", Style::Points, times.iter().zip(cfgs).map(|(t, cfg)| (*t, cfg.deadline as f64)).collect());
+
+    let mut required = Plot::new("Bandwidth to meet the deadline", "send time (s)", "bits/s");
+    required
+      .series("per block", Style::Points, times.iter().copied().zip(self.required_rates.iter().copied()).collect())
+      .series("minimum for all", Style::Line, vec![(0.0, self.min_bandwidth), (self.duration, self.min_bandwidth)]);
+
+    vec![("load", load.to_svg()), ("sizes", sizes.to_svg()), ("deadlines", deadlines.to_svg()), ("required", required.to_svg())]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::trace::from_send_times;
+
+  #[test]
+  fn distribution() {
+    let values: Vec<f64> = (1..=100).map(f64::from).collect();
+    let d = Distribution::of(&values);
+    assert_eq!((100, 1.0, 50.5, 50.0, 90.0, 99.0, 100.0), (d.count, d.min, d.mean, d.p50, d.p90, d.p99, d.max));
+    assert_eq!(Distribution::default(), Distribution::of(&[]));
+  }
+
+  #[test]
+  fn required() {
+    // 1000 bytes due in 100ms need 80 kbit/s alone, but the second block
+    // is queued behind the first and both must arrive by 0.15s
+    let cfgs = from_send_times(vec![(0.0, 100, 1000, 1), (0.05, 100, 1000, 1), (1.0, 1000, 1000, 1)]);
+    let rates = required_rates(&cfgs);
+    assert!((rates[0] - 80_000.0).abs() < 1e-6);
+    // send_time_gap is an f32
+    assert!((rates[1] - 16_000.0 / 0.15).abs() < 1e-2);
+    // the 24 kbit of all the blocks between 0s and 2s
+    assert!((rates[2] - 12_000.0).abs() < 1e-2);
+  }
+
+  #[test]
+  fn stats() {
+    let cfgs = from_send_times(vec![(0.0, 100, 1000, 1), (0.5, 200, 3000, 2), (1.5, 200, 2000, 1)]);
+    let stats = TraceStats::compute(&cfgs, 1.0);
+    assert_eq!((3, 6000), (stats.blocks, stats.bytes));
+    assert_eq!(1.5, stats.duration);
+    assert!((stats.last_deadline - 1.7).abs() < 1e-9);
+    assert_eq!(vec![32_000.0, 16_000.0], stats.load);
+    assert_eq!(32_000.0, stats.peak_load);
+    assert_eq!(32_000.0, stats.average_load);
+    assert_eq!(vec![1, 2], stats.sizes.keys().copied().collect::<Vec<_>>());
+    assert_eq!(1500.0, stats.sizes[&1].mean);
+    assert_eq!(vec![(100, 1), (200, 2)], stats.deadline_counts.into_iter().collect::<Vec<_>>());
+    assert!((stats.min_bandwidth - 120_000.0).abs() < 1e-6);
+
+    let plots = TraceStats::compute(&cfgs, 1.0).plots(&cfgs);
+    assert_eq!(vec!["load", "sizes", "deadlines", "required"], plots.iter().map(|(name, _)| *name).collect::<Vec<_>>());
+  }
+}
@@ -0,0 +1,254 @@
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::dtp_config;
+use crate::trace::{from_send_times, send_times};
+use crate::tracegen::Rng;
+
+fn check_factor(factor: f64) -> Result<(), String> {
+  if !factor.is_finite() || factor < 0.0 {
+    return Err(format!("invalid factor {}", factor));
+  }
+  Ok(())
+}
+
+/// Block sizes multiplied by `factor`, rounded and at least 1 byte
+pub fn scale_sizes(cfgs: &[dtp_config], factor: f64) -> Result<Vec<dtp_config>, String> {
+  check_factor(factor)?;
+  Ok(cfgs
+    .iter()
+    .map(|cfg| {
+      let size = ((cfg.block_size as f64 * factor).round() as u64).max(1);
+      dtp_config { block_size: size, ..*cfg }
+    })
+    .collect())
+}
+
+/// Send time gaps multiplied by `factor`, 2 plays the trace at half speed
+pub fn scale_gaps(cfgs: &[dtp_config], factor: f64) -> Result<Vec<dtp_config>, String> {
+  check_factor(factor)?;
+  Ok(cfgs
+    .iter()
+    .map(|cfg| dtp_config { send_time_gap: (cfg.send_time_gap as f64 * factor) as f32, ..*cfg })
+    .collect())
+}
+
+/// Indices of the blocks sent in `[start, end)` s
+pub fn slice_range(cfgs: &[dtp_config], start: f64, end: f64) -> Range<usize> {
+  let times = send_times(cfgs);
+  let first = times.iter().position(|t| *t >= start).unwrap_or(times.len());
+  let last = times.iter().position(|t| *t >= end).unwrap_or(times.len()).max(first);
+  first..last
+}
+
+/// The blocks sent in `[start, end)` s, moved `start` s earlier
+pub fn slice(cfgs: &[dtp_config], start: f64, end: f64) -> Vec<dtp_config> {
+  let range = slice_range(cfgs, start, end);
+  let times = send_times(cfgs);
+  from_send_times(
+    range.map(|i| (times[i] - start.max(0.0), cfgs[i].deadline, cfgs[i].block_size, cfgs[i].priority)),
+  )
+}
+
+/// Where a block of a merged trace comes from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Source {
+  /// index of the trace given to `merge`
+  pub trace: usize,
+  /// index of the block in that trace
+  pub block: usize,
+}
+
+/// The blocks of all the traces ordered by absolute send time, with the
+/// source of every block; blocks of the same time keep the order of the
+/// traces
+pub fn merge(traces: &[Vec<dtp_config>]) -> (Vec<dtp_config>, Vec<Source>) {
+  let mut blocks = Vec::new();
+  for (trace, cfgs) in traces.iter().enumerate() {
+    for (block, (t, cfg)) in send_times(cfgs).into_iter().zip(cfgs).enumerate() {
+      blocks.push((t, *cfg, Source { trace, block }));
+    }
+  }
+  blocks.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+  let sources = blocks.iter().map(|b| b.2).collect();
+  let cfgs = from_send_times(blocks.iter().map(|(t, cfg, _)| (*t, cfg.deadline, cfg.block_size, cfg.priority)));
+  (cfgs, sources)
+}
+
+/// A random permutation of `0..n`
+pub fn shuffle_order(n: usize, seed: u64) -> Vec<usize> {
+  let mut rng = Rng::new(seed);
+  let mut order: Vec<usize> = (0..n).collect();
+  for i in (1..n).rev() {
+    let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+    order.swap(i, j);
+  }
+  order
+}
+
+/// The send times stay, the deadlines, sizes and priorities of the blocks
+/// are reordered by `shuffle_order`
+pub fn shuffle(cfgs: &[dtp_config], seed: u64) -> Vec<dtp_config> {
+  shuffle_order(cfgs.len(), seed)
+    .into_iter()
+    .zip(cfgs)
+    .map(|(i, cfg)| dtp_config { send_time_gap: cfg.send_time_gap, ..cfgs[i] })
+    .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+  Set(i32),
+  Add(i32),
+  Scale(f64),
+}
+
+/// A rewrite of a field, optionally only of the blocks of a priority
+///
+/// Written `OP[@PRIORITY]` where `OP` is `=N`, `+N`, `-N` or `*F`, e.g.
+/// `*0.5@2` halves the field of the priority 2 blocks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rule {
+  pub op: Op,
+  pub priority: Option<i32>,
+}
+
+impl FromStr for Rule {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let err = || format!("invalid rule {}, expected =N, +N, -N or *F with an optional @PRIORITY", s);
+    let (op, priority) = match s.split_once('@') {
+      Some((op, priority)) => (op, Some(priority.parse().map_err(|_| err())?)),
+      None => (s, None),
+    };
+    let op = match op.split_at_checked(1).ok_or_else(err)? {
+      ("=", n) => Op::Set(n.parse().map_err(|_| err())?),
+      ("+", n) => Op::Add(n.parse().map_err(|_| err())?),
+      ("-", n) => Op::Add(n.parse::<i32>().ok().and_then(i32::checked_neg).ok_or_else(err)?),
+      ("*", f) => {
+        let f: f64 = f.parse().map_err(|_| err())?;
+        check_factor(f)?;
+        Op::Scale(f)
+      },
+      _ => return Err(err()),
+    };
+    Ok(Rule { op, priority })
+  }
+}
+
+/// Comma separated rules
+pub fn parse_rules(s: &str) -> Result<Vec<Rule>, String> {
+  s.split(',').map(str::parse).collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+  Deadline,
+  Priority,
+}
+
+/// Rewrite `field` of every block by the first rule matching its priority,
+/// deadlines stay at least 1 ms and priorities at least 0
+pub fn rewrite(cfgs: &[dtp_config], field: Field, rules: &[Rule]) -> Vec<dtp_config> {
+  cfgs
+    .iter()
+    .map(|cfg| {
+      let rule = match rules.iter().find(|rule| rule.priority.is_none_or(|p| p == cfg.priority)) {
+        Some(rule) => rule,
+        None => return *cfg,
+      };
+      let (value, min) = match field {
+        Field::Deadline => (cfg.deadline, 1),
+        Field::Priority => (cfg.priority, 0),
+      };
+      let value = match rule.op {
+        Op::Set(n) => n,
+        Op::Add(n) => value.saturating_add(n),
+        Op::Scale(f) => (value as f64 * f).round().clamp(i32::MIN as f64, i32::MAX as f64) as i32,
+      }
+      .max(min);
+      match field {
+        Field::Deadline => dtp_config { deadline: value, ..*cfg },
+        Field::Priority => dtp_config { priority: value, ..*cfg },
+      }
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::trace::validate;
+
+  fn trace() -> Vec<dtp_config> {
+    from_send_times(vec![(0.0, 200, 1000, 1), (0.5, 200, 50000, 2), (1.0, 100, 1000, 1), (2.0, 300, 3, 3)])
+  }
+
+  fn key(cfgs: &[dtp_config]) -> Vec<(f32, i32, u64, i32)> {
+    cfgs.iter().map(|cfg| (cfg.send_time_gap, cfg.deadline, cfg.block_size, cfg.priority)).collect()
+  }
+
+  #[test]
+  fn scale() {
+    let cfgs = scale_sizes(&trace(), 0.1).unwrap();
+    assert_eq!(vec![100, 5000, 100, 1], cfgs.iter().map(|cfg| cfg.block_size).collect::<Vec<_>>());
+    let cfgs = scale_gaps(&trace(), 2.0).unwrap();
+    assert_eq!(vec![0.0, 1.0, 2.0, 4.0], send_times(&cfgs));
+    assert!(scale_sizes(&trace(), -1.0).is_err());
+    assert!(scale_gaps(&trace(), f64::NAN).is_err());
+  }
+
+  #[test]
+  fn slices() {
+    let cfgs = slice(&trace(), 0.5, 2.0);
+    assert_eq!(vec![(0.0, 200, 50000, 2), (0.5, 100, 1000, 1)], key(&cfgs));
+    assert_eq!(1..3, slice_range(&trace(), 0.5, 2.0));
+    assert_eq!(4..4, slice_range(&trace(), 3.0, 1.0));
+    assert_eq!(4, slice(&trace(), 0.0, f64::INFINITY).len());
+  }
+
+  #[test]
+  fn merged() {
+    let other = from_send_times(vec![(0.25, 50, 10, 5), (1.0, 50, 20, 5)]);
+    let (cfgs, sources) = merge(&[trace(), other]);
+    assert_eq!(Ok(()), validate(&cfgs));
+    assert_eq!(vec![0.0, 0.25, 0.5, 1.0, 1.0, 2.0], send_times(&cfgs));
+    assert_eq!(vec![0, 1, 0, 0, 1, 0], sources.iter().map(|s| s.trace).collect::<Vec<_>>());
+    assert_eq!(Source { trace: 1, block: 1 }, sources[4]);
+    assert_eq!(20, cfgs[4].block_size);
+  }
+
+  #[test]
+  fn shuffled() {
+    let cfgs = shuffle(&trace(), 3);
+    assert_eq!(send_times(&trace()), send_times(&cfgs));
+    let mut sizes: Vec<u64> = cfgs.iter().map(|cfg| cfg.block_size).collect();
+    sizes.sort();
+    assert_eq!(vec![3, 1000, 1000, 50000], sizes);
+    assert_eq!(key(&cfgs), key(&shuffle(&trace(), 3)));
+    let mut order = shuffle_order(100, 1);
+    assert_ne!((0..100).collect::<Vec<_>>(), order);
+    order.sort();
+    assert_eq!((0..100).collect::<Vec<_>>(), order);
+  }
+
+  #[test]
+  fn rules() {
+    assert_eq!(Ok(Rule { op: Op::Scale(0.5), priority: Some(2) }), "*0.5@2".parse());
+    assert_eq!(Ok(Rule { op: Op::Add(-50), priority: None }), "-50".parse());
+    assert!("50".parse::<Rule>().is_err());
+    assert!("*-1".parse::<Rule>().is_err());
+    assert!("--2147483648".parse::<Rule>().is_err());
+    assert_eq!(Ok(Rule { op: Op::Add(2147483647), priority: None }), "--2147483647".parse());
+    assert!("=1@x".parse::<Rule>().is_err());
+    assert!("".parse::<Rule>().is_err());
+
+    let cfgs = rewrite(&trace(), Field::Deadline, &parse_rules("*0.5@2,-150").unwrap());
+    assert_eq!(vec![50, 100, 1, 150], cfgs.iter().map(|cfg| cfg.deadline).collect::<Vec<_>>());
+    // the first matching rule wins, so priorities can be swapped
+    let cfgs = rewrite(&trace(), Field::Priority, &parse_rules("=2@1,=1@2,-5@3").unwrap());
+    assert_eq!(vec![2, 1, 2, 0], cfgs.iter().map(|cfg| cfg.priority).collect::<Vec<_>>());
+    assert_eq!(Ok(()), validate(&cfgs));
+  }
+}
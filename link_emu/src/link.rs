@@ -0,0 +1,282 @@
+use std::cmp::{max, min};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::trace::{MahimahiTrace, MAHIMAHI_MTU};
+
+/// How fast the bottleneck drains its queue.
+#[derive(Clone, Debug)]
+pub enum Shaper {
+    Unlimited,
+    /// Fixed rate in bits per second
+    Rate(u64),
+    Trace(Arc<MahimahiTrace>),
+}
+
+/// Parameters of one direction of the emulated link.
+#[derive(Clone, Debug)]
+pub struct LinkConfig {
+    pub shaper: Shaper,
+    pub delay_us: u64,
+    pub jitter_us: u64,
+    /// Bottleneck buffer in bytes, 0 means unlimited
+    pub queue_bytes: usize,
+    pub seed: u64,
+}
+
+struct Packet {
+    data: Vec<u8>,
+    offset: usize,
+    depart: u64,  // us, leaves the bottleneck
+    deliver: u64, // us, arrives at the receiver
+}
+
+/// One direction of the emulated link.
+///
+/// Bytes pushed into the link are cut into `MAHIMAHI_MTU` sized packets. Each
+/// packet waits in the bottleneck queue until the shaper lets it leave, then
+/// travels for `delay + jitter` before it can be written to the receiver.
+/// Packets are never reordered or dropped, because both ends of the relay are
+/// byte streams. A full queue stops the relay from reading instead, which
+/// pushes back on the sender through TCP flow control. So does a receiver
+/// that does not take the packets that reached it, the relay stops reading
+/// until it does.
+pub struct Link {
+    config: LinkConfig,
+    packets: VecDeque<Packet>,
+    departed: usize, // packets[..departed] have left the bottleneck
+    queued_bytes: usize,
+    last_depart: u64,
+    last_deliver: u64,
+    // state of the trace shaper
+    next_opportunity: u64,
+    credit: usize,
+    credit_at: u64,
+    rng: u64,
+}
+
+impl Link {
+    pub fn new(config: LinkConfig) -> Self {
+        let rng = max(config.seed, 1);
+        Link {
+            config,
+            packets: VecDeque::new(),
+            departed: 0,
+            queued_bytes: 0,
+            last_depart: 0,
+            last_deliver: 0,
+            next_opportunity: 0,
+            credit: 0,
+            credit_at: 0,
+            rng,
+        }
+    }
+
+    /// Bytes that can be accepted before the bottleneck buffer is full.
+    pub fn room(&self) -> usize {
+        if self.config.queue_bytes == 0 {
+            usize::MAX
+        } else {
+            self.config.queue_bytes.saturating_sub(self.queued_bytes)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    pub fn queued_bytes(&self) -> usize {
+        self.queued_bytes
+    }
+
+    /// Put `buf` into the link at time `now` (us).
+    pub fn push(&mut self, now: u64, buf: &[u8]) {
+        for chunk in buf.chunks(MAHIMAHI_MTU) {
+            let depart = self.departure(now, chunk.len());
+            let jitter = if self.config.jitter_us > 0 {
+                self.next_random() % (self.config.jitter_us + 1)
+            } else {
+                0
+            };
+            let deliver = max(self.last_deliver, depart + self.config.delay_us + jitter);
+            self.last_depart = depart;
+            self.last_deliver = deliver;
+            self.queued_bytes += chunk.len();
+            self.packets.push_back(Packet {
+                data: chunk.to_vec(),
+                offset: 0,
+                depart,
+                deliver,
+            });
+        }
+    }
+
+    /// Move every packet that has left the bottleneck by `now` out of the queue.
+    pub fn advance(&mut self, now: u64) {
+        while self.departed < self.packets.len() && self.packets[self.departed].depart <= now {
+            self.queued_bytes -= self.packets[self.departed].data.len();
+            self.departed += 1;
+        }
+    }
+
+    /// Bytes of the first packet if it has reached the receiver by `now`.
+    pub fn ready(&self, now: u64) -> Option<&[u8]> {
+        match self.packets.front() {
+            Some(pkt) if pkt.deliver <= now => Some(&pkt.data[pkt.offset..]),
+            _ => None,
+        }
+    }
+
+    /// Mark `size` bytes of the first packet as written to the receiver.
+    pub fn consume(&mut self, size: usize) {
+        let finished = match self.packets.front_mut() {
+            Some(pkt) => {
+                pkt.offset += size;
+                pkt.offset >= pkt.data.len()
+            }
+            None => false,
+        };
+        if finished {
+            self.packets.pop_front();
+            self.departed -= 1;
+        }
+    }
+
+    /// The next time at which `advance` or `ready` would change their result.
+    pub fn next_event(&self) -> Option<u64> {
+        let depart = self.next_departure();
+        let deliver = self.packets.front().map(|pkt| pkt.deliver);
+        match (depart, deliver) {
+            (Some(a), Some(b)) => Some(min(a, b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// The next time a packet leaves the bottleneck, what `advance` waits for
+    /// while the delivered packets cannot be written.
+    pub fn next_departure(&self) -> Option<u64> {
+        self.packets.get(self.departed).map(|pkt| pkt.depart)
+    }
+
+    // time at which a packet of `len` bytes arriving at `now` leaves the bottleneck
+    fn departure(&mut self, now: u64, len: usize) -> u64 {
+        let start = max(now, self.last_depart);
+        match self.config.shaper {
+            Shaper::Unlimited => start,
+            Shaper::Rate(bps) => start + (len as u64 * 8 * 1_000_000).div_ceil(bps.max(1)),
+            Shaper::Trace(ref trace) => {
+                let mut need = len;
+                let mut depart = start;
+                // leftover bytes of an opportunity can only be used by packets
+                // that were already waiting when it happened
+                if self.credit > 0 && now <= self.credit_at {
+                    let used = min(self.credit, need);
+                    self.credit -= used;
+                    need -= used;
+                    depart = self.credit_at;
+                } else {
+                    self.credit = 0;
+                }
+                if need > 0 {
+                    self.next_opportunity = max(self.next_opportunity, trace.first_at_or_after(start));
+                    while need > 0 {
+                        depart = trace.opportunity_us(self.next_opportunity);
+                        self.next_opportunity += 1;
+                        let used = min(MAHIMAHI_MTU, need);
+                        need -= used;
+                        self.credit = MAHIMAHI_MTU - used;
+                        self.credit_at = depart;
+                    }
+                }
+                depart
+            }
+        }
+    }
+
+    // xorshift64, good enough for jitter and reproducible from the seed
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(shaper: Shaper) -> LinkConfig {
+        LinkConfig {
+            shaper,
+            delay_us: 0,
+            jitter_us: 0,
+            queue_bytes: 0,
+            seed: 1,
+        }
+    }
+
+    #[test]
+    fn rate() {
+        // 12 Mbit/s: one 1500 B packet per millisecond
+        let mut cfg = config(Shaper::Rate(12_000_000));
+        cfg.delay_us = 10_000;
+        cfg.queue_bytes = 3000;
+        let mut link = Link::new(cfg);
+        link.push(0, &[0; 3000]);
+        assert_eq!(0, link.room());
+        assert_eq!(Some(1000), link.next_event());
+        link.advance(1000);
+        assert_eq!(1500, link.room());
+        assert!(link.ready(10_999).is_none());
+        assert_eq!(1500, link.ready(11_000).unwrap().len());
+        link.consume(1000);
+        assert_eq!(500, link.ready(11_000).unwrap().len());
+        link.consume(500);
+        assert_eq!(Some(2000), link.next_event());
+        link.advance(2000);
+        assert_eq!(1500, link.ready(12_000).unwrap().len());
+        link.consume(1500);
+        assert!(link.is_empty());
+        assert_eq!(3000, link.room());
+    }
+
+    #[test]
+    fn trace() {
+        let trace = MahimahiTrace::parse("1\n1\n4\n").unwrap();
+        let mut link = Link::new(config(Shaper::Trace(Arc::new(trace))));
+        link.push(0, &[0; 2000]);
+        // two packets share the two opportunities at 1 ms
+        assert_eq!(Some(1000), link.next_event());
+        link.advance(1000);
+        assert_eq!(0, link.queued_bytes());
+        // the leftover of the second opportunity is gone by now
+        link.push(2000, &[0; 100]);
+        link.advance(3999);
+        assert_eq!(100, link.queued_bytes());
+        link.advance(4000);
+        assert_eq!(0, link.queued_bytes());
+        // the trace loops with a 4 ms period
+        link.push(4500, &[0; 1500]);
+        link.advance(5000);
+        assert_eq!(0, link.queued_bytes());
+    }
+
+    #[test]
+    fn jitter_keeps_order() {
+        let mut cfg = config(Shaper::Unlimited);
+        cfg.delay_us = 1000;
+        cfg.jitter_us = 5000;
+        let mut link = Link::new(cfg);
+        for i in 0..100 {
+            link.push(i * 10, &[0; 10]);
+        }
+        let mut last = 0;
+        for pkt in link.packets.iter() {
+            assert!(pkt.deliver >= last);
+            assert!(pkt.deliver >= pkt.depart + 1000);
+            assert!(pkt.deliver <= 990 + 1000 + 5000);
+            last = pkt.deliver;
+        }
+    }
+}
@@ -0,0 +1,235 @@
+#[macro_use]
+extern crate log;
+
+use std::error::Error;
+use std::io::prelude::*;
+use std::net::{Shutdown, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{io, net, thread};
+
+use mio::net::TcpStream;
+use mio::{Events, Interest, Poll, Token};
+
+use link::{Link, LinkConfig, Shaper};
+use trace::MahimahiTrace;
+
+const USAGE: &str = "Usage:
+link_emu [options] LADDR LPORT SADDR SPORT
+link_emu -h | --help
+
+Options:
+--bandwidth MBPS         Bottleneck rate in Mbit/s, 0 means unlimited [default: 0].
+--delay MS               One-way propagation delay in milliseconds [default: 0].
+--jitter MS              Maximum extra random delay of a packet in milliseconds [default: 0].
+--queue BYTES            Bottleneck buffer size in bytes, 0 means unlimited [default: 0].
+--downlink-trace PATH    Mahimahi trace for server to client, overrides --bandwidth.
+--uplink-trace PATH      Mahimahi trace for client to server, overrides --bandwidth.
+--seed SEED              Seed of the jitter generator [default: 1].
+-h --help                Show this screen.
+";
+
+const CLIENT: Token = Token(0);
+const SERVER: Token = Token(1);
+// longest time to sleep when there is nothing scheduled
+const IDLE_TIMEOUT: u64 = 100_000; // us
+const READ_BUF_SIZE: usize = 65535;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = docopt::Docopt::new(USAGE)
+        .and_then(|dopt| dopt.parse())
+        .unwrap_or_else(|e| e.exit());
+
+    env_logger::builder().format_timestamp_nanos().init();
+
+    let listen_addr = format!("{}:{}", args.get_str("LADDR"), args.get_str("LPORT"))
+        .parse::<SocketAddr>()?;
+    let server_addr = format!("{}:{}", args.get_str("SADDR"), args.get_str("SPORT"))
+        .parse::<SocketAddr>()?;
+
+    let bandwidth: f64 = args.get_str("--bandwidth").parse()?;
+    // a rate that truncates to 0 bit/s would never send anything
+    if !bandwidth.is_finite() || bandwidth < 0.0 || (bandwidth > 0.0 && bandwidth * 1_000_000.0 < 1.0) {
+        return Err(format!("invalid bandwidth {}, expect 0 or at least 1 bit/s", bandwidth).into());
+    }
+    let rate = if bandwidth > 0.0 {
+        Shaper::Rate((bandwidth * 1_000_000.0) as u64)
+    } else {
+        Shaper::Unlimited
+    };
+    let shaper = |option: &str| -> Result<Shaper, Box<dyn Error>> {
+        match args.get_str(option) {
+            "" => Ok(rate.clone()),
+            path => Ok(Shaper::Trace(Arc::new(MahimahiTrace::from_file(path)?))),
+        }
+    };
+    let delay_us = (args.get_str("--delay").parse::<f64>()? * 1000.0) as u64;
+    let jitter_us = (args.get_str("--jitter").parse::<f64>()? * 1000.0) as u64;
+    let queue_bytes: usize = args.get_str("--queue").parse()?;
+    let seed: u64 = args.get_str("--seed").parse()?;
+
+    let downlink = LinkConfig {
+        shaper: shaper("--downlink-trace")?,
+        delay_us,
+        jitter_us,
+        queue_bytes,
+        seed,
+    };
+    let uplink = LinkConfig {
+        shaper: shaper("--uplink-trace")?,
+        seed: seed.wrapping_add(1),
+        ..downlink.clone()
+    };
+
+    let listener = net::TcpListener::bind(listen_addr)?;
+    eprintln!("link_emu listening on {}, relay to {}", listen_addr, server_addr);
+    for stream in listener.incoming() {
+        let client = stream?;
+        let (uplink, downlink) = (uplink.clone(), downlink.clone());
+        thread::spawn(move || {
+            let peer = client.peer_addr().ok();
+            match relay(client, server_addr, uplink, downlink) {
+                Ok(()) => eprintln!("relay of {:?} finished", peer),
+                Err(e) => eprintln!("relay of {:?} failed: {}", peer, e),
+            }
+        });
+    }
+    Ok(())
+}
+
+/// One direction of a relayed connection.
+struct Direction {
+    link: Link,
+    eof: bool,
+    shutdown: bool,
+    // the last write would block, a due packet waits for `dst` to be writable
+    blocked: bool,
+}
+
+impl Direction {
+    fn new(config: LinkConfig) -> Self {
+        Direction {
+            link: Link::new(config),
+            eof: false,
+            shutdown: false,
+            blocked: false,
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.shutdown
+    }
+
+    // when the link needs attention again, a blocked direction is woken up
+    // by the WRITABLE event instead
+    fn next_event(&self) -> Option<u64> {
+        if self.blocked {
+            self.link.next_departure()
+        } else {
+            self.link.next_event()
+        }
+    }
+}
+
+// move bytes from `src` through the link to `dst` as far as time allows
+fn pump(
+    dir: &mut Direction,
+    src: &mut TcpStream,
+    dst: &mut TcpStream,
+    buf: &mut [u8],
+    start: Instant,
+) -> io::Result<()> {
+    let now = || start.elapsed().as_micros() as u64;
+    deliver(dir, dst, now())?;
+    // the delivered packets that the receiver does not take are not in the
+    // bottleneck queue, so reading stops while it pushes back
+    while !dir.eof && !dir.blocked && dir.link.room() > 0 {
+        let size = std::cmp::min(buf.len(), dir.link.room());
+        match src.read(&mut buf[..size]) {
+            Ok(0) => dir.eof = true,
+            Ok(len) => {
+                dir.link.push(now(), &buf[..len]);
+                deliver(dir, dst, now())?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => dir.eof = true,
+            Err(e) => return Err(e),
+        }
+    }
+    deliver(dir, dst, now())?;
+    if dir.eof && dir.link.is_empty() && !dir.shutdown {
+        dst.shutdown(Shutdown::Write).ok();
+        dir.shutdown = true;
+    }
+    Ok(())
+}
+
+// write the packets that have reached `dst` by `t`
+fn deliver(dir: &mut Direction, dst: &mut TcpStream, t: u64) -> io::Result<()> {
+    dir.link.advance(t);
+    dir.blocked = false;
+    while let Some(bytes) = dir.link.ready(t) {
+        match dst.write(bytes) {
+            Ok(size) => dir.link.consume(size),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                dir.blocked = true;
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn relay(
+    client: net::TcpStream,
+    server_addr: SocketAddr,
+    uplink: LinkConfig,
+    downlink: LinkConfig,
+) -> io::Result<()> {
+    let server = net::TcpStream::connect(server_addr)?;
+    client.set_nonblocking(true)?;
+    server.set_nonblocking(true)?;
+    client.set_nodelay(true)?;
+    server.set_nodelay(true)?;
+    let mut client = TcpStream::from_std(client);
+    let mut server = TcpStream::from_std(server);
+
+    let mut poll = Poll::new()?;
+    let mut events = Events::with_capacity(1024);
+    let interest = Interest::READABLE | Interest::WRITABLE;
+    poll.registry().register(&mut client, CLIENT, interest)?;
+    poll.registry().register(&mut server, SERVER, interest)?;
+
+    let mut up = Direction::new(uplink);
+    let mut down = Direction::new(downlink);
+    let mut buf = vec![0; READ_BUF_SIZE];
+    let start = Instant::now();
+    loop {
+        // sockets are polled on every iteration, so the edge triggered events
+        // only serve as wakeups
+        pump(&mut up, &mut client, &mut server, &mut buf, start)?;
+        pump(&mut down, &mut server, &mut client, &mut buf, start)?;
+        if up.finished() && down.finished() {
+            break;
+        }
+        trace!(
+            "queued: uplink {} B, downlink {} B",
+            up.link.queued_bytes(),
+            down.link.queued_bytes()
+        );
+
+        let now = start.elapsed().as_micros() as u64;
+        let timeout = [up.next_event(), down.next_event()]
+            .iter()
+            .flatten()
+            .map(|&t| t.saturating_sub(now))
+            .min()
+            .unwrap_or(IDLE_TIMEOUT);
+        poll.poll(&mut events, Some(Duration::from_micros(timeout)))?;
+    }
+    Ok(())
+}
+
+mod link;
+mod trace;
@@ -0,0 +1,99 @@
+use std::fs;
+use std::io;
+
+/// Bytes that can be delivered at a single delivery opportunity.
+pub const MAHIMAHI_MTU: usize = 1500;
+
+/// A Mahimahi-style bandwidth trace.
+///
+/// Every line of the file is a timestamp in milliseconds at which one
+/// `MAHIMAHI_MTU` sized packet can leave the link. Repeated timestamps mean
+/// several packets in the same millisecond. The trace loops with a period equal
+/// to its last timestamp.
+#[derive(Clone, Debug)]
+pub struct MahimahiTrace {
+    opportunities: Vec<u64>, // ms
+    period: u64,             // ms
+}
+
+impl MahimahiTrace {
+    pub fn from_file(path: &str) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        MahimahiTrace::parse(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut opportunities = Vec::new();
+        for (lineno, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let ts: u64 = line
+                .parse()
+                .map_err(|_| format!("line {}: invalid timestamp {:?}", lineno + 1, line))?;
+            if let Some(&last) = opportunities.last() {
+                if ts < last {
+                    return Err(format!("line {}: timestamps must not decrease", lineno + 1));
+                }
+            }
+            opportunities.push(ts);
+        }
+        let period = match opportunities.last() {
+            Some(&last) if last > 0 => last,
+            _ => return Err("trace must contain a positive timestamp".to_string()),
+        };
+        Ok(MahimahiTrace { opportunities, period })
+    }
+
+    /// Time of the k-th delivery opportunity in microseconds, counting across loops.
+    pub fn opportunity_us(&self, k: u64) -> u64 {
+        let n = self.opportunities.len() as u64;
+        ((k / n) * self.period + self.opportunities[(k % n) as usize]) * 1000
+    }
+
+    /// Index of the first opportunity at or after `t_us`.
+    pub fn first_at_or_after(&self, t_us: u64) -> u64 {
+        let n = self.opportunities.len() as u64;
+        let period_us = self.period * 1000;
+        // the last opportunity of a loop may coincide with the start of the next one
+        let mut loops = (t_us / period_us).saturating_sub(1);
+        loop {
+            let offset_us = t_us.saturating_sub(loops * period_us);
+            let idx = self.opportunities.partition_point(|&ts| ts * 1000 < offset_us) as u64;
+            if idx < n {
+                return loops * n + idx;
+            }
+            loops += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let trace = MahimahiTrace::parse("1\n1\n3\n\n5\n").unwrap();
+        assert_eq!(5, trace.period);
+        assert_eq!(1000, trace.opportunity_us(0));
+        assert_eq!(1000, trace.opportunity_us(1));
+        assert_eq!(5000, trace.opportunity_us(3));
+        assert_eq!(6000, trace.opportunity_us(4));
+        assert!(MahimahiTrace::parse("3\n1\n").is_err());
+        assert!(MahimahiTrace::parse("0\n").is_err());
+        assert!(MahimahiTrace::parse("abc\n").is_err());
+    }
+
+    #[test]
+    fn first_at_or_after() {
+        let trace = MahimahiTrace::parse("1\n1\n3\n5\n").unwrap();
+        assert_eq!(0, trace.first_at_or_after(0));
+        assert_eq!(0, trace.first_at_or_after(1000));
+        assert_eq!(2, trace.first_at_or_after(1001));
+        assert_eq!(3, trace.first_at_or_after(5000));
+        assert_eq!(4, trace.first_at_or_after(5001));
+        assert_eq!(6, trace.first_at_or_after(7000));
+    }
+}
@@ -0,0 +1,73 @@
+#![no_main]
+use std::cmp::min;
+use std::collections::VecDeque;
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use tcp_client::loopbytes::LoopBytes;
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Push(Vec<u8>),
+    TryPush(Vec<u8>),
+    ReadFrom(Vec<u8>),
+    Pop(u8),
+    Peek(u8),
+    Drop(u8),
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    capacity: u8,
+    extra: u8,
+    ops: Vec<Op>,
+}
+
+// LoopBytes must behave like a VecDeque holding at most `max_capacity - 1` bytes
+fuzz_target!(|input: Input| {
+    let capacity = input.capacity as usize + 2;
+    let max_capacity = capacity + input.extra as usize;
+    let mut loopbytes = LoopBytes::with_max_capacity(capacity, max_capacity);
+    let mut model: VecDeque<u8> = VecDeque::new();
+    let mut buf = [0; 256];
+    for op in input.ops {
+        let room = max_capacity - 1 - model.len();
+        match op {
+            Op::Push(bytes) => {
+                let pushed = loopbytes.push(&bytes, bytes.len());
+                assert_eq!(pushed, min(room, bytes.len()));
+                model.extend(&bytes[..pushed]);
+            }
+            Op::TryPush(bytes) => {
+                let ok = loopbytes.try_push(&bytes).is_ok();
+                assert_eq!(ok, bytes.len() <= room);
+                if ok {
+                    model.extend(&bytes);
+                }
+            }
+            Op::ReadFrom(bytes) => {
+                let mut reader = &bytes[..];
+                let read = loopbytes.read_from(&mut reader).unwrap();
+                assert_eq!(read, min(room, bytes.len()));
+                model.extend(&bytes[..read]);
+            }
+            Op::Pop(n) => {
+                let popped = loopbytes.pop(&mut buf, n as usize);
+                let expected: Vec<u8> = model.drain(..min(n as usize, model.len())).collect();
+                assert_eq!(&buf[..popped], &expected[..]);
+            }
+            Op::Peek(n) => {
+                let peeked = loopbytes.peek(&mut buf, n as usize);
+                let expected: Vec<u8> = model.iter().take(n as usize).cloned().collect();
+                assert_eq!(&buf[..peeked], &expected[..]);
+            }
+            Op::Drop(n) => {
+                let dropped = loopbytes.drop(n as usize);
+                assert_eq!(dropped, min(n as usize, model.len()));
+                model.drain(..dropped);
+            }
+        }
+        let (first, second) = loopbytes.as_slices();
+        assert_eq!([first, second].concat(), model.iter().cloned().collect::<Vec<u8>>());
+    }
+});
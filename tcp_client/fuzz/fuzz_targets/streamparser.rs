@@ -0,0 +1,25 @@
+#![no_main]
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use tcp_client::streamparser::StreamParser;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    capacity: u8,
+    chunks: Vec<u16>,
+    stream: Vec<u8>,
+}
+
+// arbitrary bytes in arbitrary chunks must never make the parser panic
+fuzz_target!(|input: Input| {
+    let mut parser = StreamParser::new(input.capacity as usize + 1);
+    let mut chunks = input.chunks.iter().map(|&c| c as usize + 1).cycle();
+    let mut offset = 0;
+    while offset < input.stream.len() {
+        let end = std::cmp::min(offset + chunks.next().unwrap_or(usize::MAX), input.stream.len());
+        while offset < end {
+            offset += parser.recv(&input.stream[offset..end], end - offset);
+            parser.consume();
+        }
+    }
+});
@@ -0,0 +1,79 @@
+#![no_main]
+use arbitrary::Arbitrary;
+use dtp_utils::crc32c::crc32c;
+use dtp_utils::{get_current_usec, header_len, BlockHeader, ABORT_MAGIC};
+use libfuzzer_sys::fuzz_target;
+use tcp_client::streamparser::{ParseError, StreamParser};
+
+#[derive(Arbitrary, Debug)]
+struct Block {
+    garbage: Vec<u8>,
+    block_size: u16,
+    priority: u8,
+    deadline: u16,
+    checksum: bool,
+    // overwrite one header byte
+    corrupt: Option<(u8, u8)>,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    capacity: u8,
+    chunks: Vec<u16>,
+    blocks: Vec<Block>,
+}
+
+// block streams with garbage and corrupted headers in arbitrary chunks: the
+// parser must not panic, and must recover every block of a clean stream
+fuzz_target!(|input: Input| {
+    let mut stream = vec![];
+    let mut clean = true;
+    for (i, block) in input.blocks.iter().enumerate() {
+        // a block magic, or an abort message if it starts at a block boundary
+        if block.garbage.windows(4).any(|w| header_len(w).is_some() || w == ABORT_MAGIC) {
+            clean = false;
+        }
+        stream.extend_from_slice(&block.garbage);
+        let hdr = BlockHeader {
+            id: i as u64,
+            start_timestamp: get_current_usec(),
+            block_size: block.block_size as u64 + 1,
+            priority: block.priority as u64,
+            deadline: block.deadline as u64,
+            // of the zeros below
+            checksum: if block.checksum {
+                Some(crc32c(&vec![0; block.block_size as usize + 1]))
+            } else {
+                None
+            },
+        };
+        let mut bytes = hdr.to_bytes();
+        if let Some((offset, value)) = block.corrupt {
+            let offset = offset as usize % hdr.wire_len();
+            clean &= bytes[offset] == value;
+            bytes[offset] = value;
+        }
+        stream.extend_from_slice(&bytes);
+        stream.resize(stream.len() + hdr.block_size as usize, 0);
+    }
+
+    let mut parser = StreamParser::new(input.capacity as usize + 1);
+    let mut chunks = input.chunks.iter().map(|&c| c as usize + 1).cycle();
+    let mut offset = 0;
+    let mut ids = vec![];
+    while offset < stream.len() {
+        let end = std::cmp::min(offset + chunks.next().unwrap_or(usize::MAX), stream.len());
+        while offset < end {
+            offset += parser.recv(&stream[offset..end], end - offset);
+            for res in parser.consume() {
+                match res {
+                    Ok(block) | Err(ParseError::ClockSkew(block)) => ids.push(block.id),
+                    Err(_) => (),
+                }
+            }
+        }
+    }
+    if clean {
+        assert_eq!(ids, (0..input.blocks.len() as u64).collect::<Vec<u64>>());
+    }
+});
@@ -0,0 +1,37 @@
+use std::error::Error;
+use std::fs;
+
+use tcp_client::latency::LatencyHistograms;
+
+const USAGE: &str = "Usage:
+    hist_merge [options] LOG...
+    hist_merge -h | --help
+
+    Add up the BCT histograms written by `client --histograms LOG` over
+    repeated runs and print their quantiles.
+
+    Options:
+    --output PATH            Also write the merged histograms as an interval log.
+    -h --help                Show this screen.
+";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = docopt::Docopt::new(USAGE)
+    .and_then(|dopt| dopt.parse())
+    .unwrap_or_else(|e| e.exit());
+
+    let logs = args.get_vec("LOG");
+    let mut merged = LatencyHistograms::new(Vec::new());
+    for log in logs.iter() {
+        let histograms = LatencyHistograms::read_log(&fs::read(log)?)
+            .map_err(|e| format!("{}: {}", log, e))?;
+        merged.merge(&histograms)?;
+    }
+    print!("{}", merged.table());
+    if !args.get_str("--output").is_empty() {
+        let mut out = Vec::new();
+        merged.write_log(&mut out, dtp_utils::get_current_usec(), 0)?;
+        fs::write(args.get_str("--output"), out)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tcp_client::streamparser::{ParseError, StreamParser};
+
+const USAGE: &str = "Usage:
+    replay [options] DUMP
+    replay -h | --help
+
+    Re-feed the files written by `client --dump-packets DUMP` through the
+    stream parser, one file per read() as in the original run.
+
+    Options:
+    --capacity SIZE          Size of the parser buffer [default: 65535].
+    -v --verbose             Print every chunk.
+    -h --help                Show this screen.
+";
+
+// `N.pkt` files of a dump directory in read() order
+fn dump_files(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files: Vec<(u64, PathBuf)> = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let index = match (path.extension(), path.file_stem()) {
+            (Some(ext), Some(stem)) if ext == "pkt" => stem.to_string_lossy().parse::<u64>().ok(),
+            _ => None,
+        };
+        if let Some(index) = index {
+            files.push((index, path));
+        }
+    }
+    files.sort();
+    for (expected, (index, _)) in files.iter().enumerate() {
+        if *index != expected as u64 {
+            return Err(format!("{}.pkt is missing from {}", expected, dir.display()).into());
+        }
+    }
+    Ok(files.into_iter().map(|(_, path)| path).collect())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = docopt::Docopt::new(USAGE)
+    .and_then(|dopt| dopt.parse())
+    .unwrap_or_else(|e| e.exit());
+
+    let dir = Path::new(args.get_str("DUMP"));
+    let capacity: usize = args.get_str("--capacity").parse()?;
+    let verbose = args.get_bool("--verbose");
+
+    let files = dump_files(dir)?;
+    let mut parser = StreamParser::new(capacity);
+    let mut total_bytes: u64 = 0;
+    let mut blocks: u64 = 0;
+    let mut complete_bytes: u64 = 0;
+    let mut errors: u64 = 0;
+
+    println!("block_id,size,priority,deadline,start_timestamp,chunk");
+    for (chunk, path) in files.iter().enumerate() {
+        let buf = fs::read(path)?;
+        total_bytes += buf.len() as u64;
+        if verbose {
+            eprintln!("chunk {}: {} bytes", chunk, buf.len());
+        }
+        let mut total_size = 0;
+        while total_size < buf.len() {
+            total_size += parser.recv(&buf[total_size..], buf.len() - total_size);
+            for res in parser.consume() {
+                let block = match res {
+                    Ok(block) | Err(ParseError::ClockSkew(block)) => block,
+                    Err(e) => {
+                        errors += 1;
+                        eprintln!("chunk {}: {}", chunk, e);
+                        continue;
+                    }
+                };
+                blocks += 1;
+                complete_bytes += block.block_size;
+                println!("{},{},{},{},{},{}",
+                    block.id,
+                    block.block_size,
+                    block.priority,
+                    block.deadline,
+                    block.start_timestamp,
+                    chunk
+                );
+            }
+        }
+    }
+    eprintln!("chunks={}, total_bytes={}, blocks={}, complete_bytes={}, parse_errors={}",
+        files.len(),
+        total_bytes,
+        blocks,
+        complete_bytes,
+        errors
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_files_order() {
+        let dir = std::env::temp_dir().join(format!("replay_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["10.pkt", "2.pkt", "0.pkt", "1.pkt", "notes.txt"].iter() {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        assert!(dump_files(&dir).is_err());
+        for i in 3..10 {
+            fs::write(dir.join(format!("{}.pkt", i)), b"").unwrap();
+        }
+        let names: Vec<String> = dump_files(&dir)
+            .unwrap()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(11, names.len());
+        assert_eq!("2.pkt", names[2]);
+        assert_eq!("10.pkt", names[10]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::{Duration, UNIX_EPOCH};
+
+use base64::Engine;
+use hdrhistogram::serialization::interval_log::{IntervalLogIterator, IntervalLogWriterBuilder, LogEntry, Tag};
+use hdrhistogram::serialization::{Deserializer, V2DeflateSerializer};
+use hdrhistogram::Histogram;
+use serde::Serialize;
+
+use crate::BlockInfo;
+
+/// Highest BCT kept exactly, one hour in us. Larger ones are clamped.
+const MAX_BCT: u64 = 3_600_000_000;
+const SIGNIFICANT_DIGITS: u8 = 3;
+
+/// The blocks a histogram is kept for
+///
+/// The `Display` form is the tag of the histogram in the exported log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Class {
+    Priority(i32),
+    /// deadlines in (lower, upper] ms, the last bucket has no upper bound
+    Deadline(i32, Option<i32>),
+    All,
+}
+
+impl fmt::Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Class::Priority(p) => write!(f, "priority={}", p),
+            Class::Deadline(lower, Some(upper)) => write!(f, "deadline={}-{}", lower, upper),
+            Class::Deadline(lower, None) => write!(f, "deadline={}-inf", lower),
+            Class::All => write!(f, "all"),
+        }
+    }
+}
+
+impl FromStr for Class {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid histogram tag {:?}", s);
+        if s == "all" {
+            return Ok(Class::All);
+        }
+        if let Some(p) = s.strip_prefix("priority=") {
+            return p.parse().map(Class::Priority).map_err(|_| invalid());
+        }
+        if let Some(range) = s.strip_prefix("deadline=") {
+            let mut parts = range.splitn(2, '-');
+            let lower = parts.next().unwrap_or("").parse().map_err(|_| invalid())?;
+            let upper = match parts.next() {
+                Some("inf") => None,
+                Some(upper) => Some(upper.parse().map_err(|_| invalid())?),
+                None => return Err(invalid()),
+            };
+            return Ok(Class::Deadline(lower, upper));
+        }
+        Err(invalid())
+    }
+}
+
+/// Quantiles of one histogram in us
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Quantiles {
+    pub count: u64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+/// HDR histograms of BCT (us) per priority and per deadline bucket
+pub struct LatencyHistograms {
+    /// upper bounds of the deadline buckets in ms, ascending
+    buckets: Vec<i32>,
+    histograms: BTreeMap<Class, Histogram<u64>>,
+}
+
+fn new_histogram() -> Histogram<u64> {
+    Histogram::new_with_bounds(1, MAX_BCT, SIGNIFICANT_DIGITS).unwrap()
+}
+
+impl LatencyHistograms {
+    pub fn new(buckets: Vec<i32>) -> Self {
+        let mut buckets = buckets;
+        buckets.sort_unstable();
+        buckets.dedup();
+        LatencyHistograms {
+            buckets,
+            histograms: BTreeMap::new(),
+        }
+    }
+
+    /// Parse bucket bounds like `100,200,500`
+    pub fn parse_buckets(s: &str) -> Result<Vec<i32>, String> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| item.parse().map_err(|_| format!("invalid deadline bucket {:?}", item)))
+            .collect()
+    }
+
+    pub fn bucket(&self, deadline: i32) -> Class {
+        let mut lower = 0;
+        for upper in self.buckets.iter() {
+            if deadline <= *upper {
+                return Class::Deadline(lower, Some(*upper));
+            }
+            lower = *upper;
+        }
+        Class::Deadline(lower, None)
+    }
+
+    /// Record the BCT of a block, from its timestamps in us
+    pub fn record(&mut self, block: &BlockInfo) {
+        let bct = block.end_timestamp.saturating_sub(block.start_timestamp);
+        for class in [Class::All, Class::Priority(block.priority), self.bucket(block.deadline)].iter() {
+            self.histograms
+                .entry(*class)
+                .or_insert_with(new_histogram)
+                .saturating_record(bct);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Class, &Histogram<u64>)> {
+        self.histograms.iter()
+    }
+
+    pub fn get(&self, class: &Class) -> Option<&Histogram<u64>> {
+        self.histograms.get(class)
+    }
+
+    /// Add the histograms of `other` to the ones of the same class
+    pub fn merge(&mut self, other: &LatencyHistograms) -> Result<(), Box<dyn Error>> {
+        for (class, histogram) in other.histograms.iter() {
+            self.histograms
+                .entry(*class)
+                .or_insert_with(new_histogram)
+                .add(histogram)?;
+        }
+        Ok(())
+    }
+
+    pub fn quantiles(&self) -> BTreeMap<String, Quantiles> {
+        self.histograms
+            .iter()
+            .map(|(class, h)| {
+                (class.to_string(), Quantiles {
+                    count: h.len(),
+                    p50: h.value_at_quantile(0.5),
+                    p90: h.value_at_quantile(0.9),
+                    p99: h.value_at_quantile(0.99),
+                    max: h.max(),
+                })
+            })
+            .collect()
+    }
+
+    /// p50/p90/p99/max tables in ms, per priority then per deadline bucket
+    pub fn table(&self) -> String {
+        let mut s = String::new();
+        let mut last_kind = None;
+        for (class, h) in self.histograms.iter() {
+            let kind = match class {
+                Class::Priority(_) => "priority",
+                Class::Deadline(..) => "deadline(ms)",
+                Class::All => "total",
+            };
+            if last_kind != Some(kind) {
+                s += &format!("{:<16}\t{:>8}\t{:>10}\t{:>10}\t{:>10}\t{:>10}\n", kind, "blocks", "p50(ms)", "p90(ms)", "p99(ms)", "max(ms)");
+                last_kind = Some(kind);
+            }
+            let name = match class {
+                Class::Priority(p) => p.to_string(),
+                Class::Deadline(lower, Some(upper)) => format!("({}, {}]", lower, upper),
+                Class::Deadline(lower, None) => format!("> {}", lower),
+                Class::All => "all".to_string(),
+            };
+            let ms = |us: u64| us as f64 / 1000.0;
+            s += &format!("{:<16}\t{:>8}\t{:>10.3}\t{:>10.3}\t{:>10.3}\t{:>10.3}\n",
+                name,
+                h.len(),
+                ms(h.value_at_quantile(0.5)),
+                ms(h.value_at_quantile(0.9)),
+                ms(h.value_at_quantile(0.99)),
+                ms(h.max())
+            );
+        }
+        s
+    }
+
+    /// Export as an HdrHistogram interval log, one interval per class tagged
+    /// with the class, covering the run from `start_time` (unix time in us)
+    /// for `duration` us
+    pub fn write_log<W: Write>(&self, out: &mut W, start_time: u64, duration: u64) -> Result<(), Box<dyn Error>> {
+        let mut serializer = V2DeflateSerializer::new();
+        let mut writer = IntervalLogWriterBuilder::new()
+            .add_comment("BCT of blocks in us, tagged by priority and deadline bucket")
+            .with_start_time(UNIX_EPOCH + Duration::from_micros(start_time))
+            .begin_log_with(out, &mut serializer)?;
+        for (class, h) in self.histograms.iter() {
+            let tag = class.to_string();
+            writer.write_histogram(h, Duration::from_secs(0), Duration::from_micros(duration), Tag::new(&tag))?;
+        }
+        Ok(())
+    }
+
+    /// Read a log written by `write_log`, histograms of the same class are added up
+    pub fn read_log(input: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut histograms = LatencyHistograms::new(Vec::new());
+        let mut deserializer = Deserializer::new();
+        for entry in IntervalLogIterator::new(input) {
+            let interval = match entry.map_err(|e| format!("invalid interval log: {:?}", e))? {
+                LogEntry::Interval(interval) => interval,
+                _ => continue,
+            };
+            let class: Class = match interval.tag() {
+                Some(tag) => tag.as_str().parse()?,
+                None => return Err("interval without a tag".into()),
+            };
+            let bytes = base64::engine::general_purpose::STANDARD.decode(interval.encoded_histogram())?;
+            let h: Histogram<u64> = deserializer.deserialize(&mut &bytes[..])?;
+            if let Class::Deadline(_, Some(upper)) = class {
+                histograms.buckets.push(upper);
+            }
+            histograms.histograms.entry(class).or_insert_with(new_histogram).add(&h)?;
+        }
+        histograms.buckets.sort_unstable();
+        histograms.buckets.dedup();
+        Ok(histograms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(priority: i32, deadline: i32, bct: u64) -> BlockInfo {
+        BlockInfo {
+            priority,
+            deadline,
+            start_timestamp: 1_000_000,
+            end_timestamp: 1_000_000 + bct,
+            ..BlockInfo::default()
+        }
+    }
+
+    #[test]
+    fn classes() {
+        let histograms = LatencyHistograms::new(LatencyHistograms::parse_buckets("200, 100").unwrap());
+        assert_eq!(Class::Deadline(0, Some(100)), histograms.bucket(100));
+        assert_eq!(Class::Deadline(100, Some(200)), histograms.bucket(101));
+        assert_eq!(Class::Deadline(200, None), histograms.bucket(5000));
+        for class in [Class::All, Class::Priority(-1), Class::Deadline(100, Some(200)), Class::Deadline(200, None)].iter() {
+            assert_eq!(Ok(*class), class.to_string().parse());
+            assert!(Tag::new(&class.to_string()).is_some());
+        }
+        assert!("deadline=100".parse::<Class>().is_err());
+        assert!(LatencyHistograms::parse_buckets("1,x").is_err());
+    }
+
+    #[test]
+    fn record_and_merge() {
+        let mut a = LatencyHistograms::new(vec![100, 200]);
+        for i in 1..=100 {
+            a.record(&block(1, 150, i * 1000));
+        }
+        a.record(&block(2, 50, 7000));
+        let q = a.quantiles();
+        assert_eq!(101, q["all"].count);
+        assert_eq!(100, q["priority=1"].count);
+        assert_eq!(100, q["deadline=100-200"].count);
+        assert!((q["priority=1"].p50 as i64 - 50_000).abs() < 100);
+        assert!((q["priority=1"].p99 as i64 - 99_000).abs() < 100);
+        assert!((q["priority=2"].max as i64 - 7000).abs() < 10);
+
+        let mut log = Vec::new();
+        a.write_log(&mut log, 1_600_000_000_000_000, 2_000_000).unwrap();
+        let read = LatencyHistograms::read_log(&log).unwrap();
+        assert_eq!(a.quantiles(), read.quantiles());
+
+        let mut merged = LatencyHistograms::read_log(&log).unwrap();
+        merged.merge(&read).unwrap();
+        assert_eq!(202, merged.get(&Class::All).unwrap().len());
+        assert_eq!(q["priority=1"].p90, merged.quantiles()["priority=1"].p90);
+        assert!(merged.table().contains("(100, 200]"));
+    }
+}
@@ -0,0 +1,5 @@
+pub mod latency;
+pub mod qoe;
+
+// the parser is part of tcp_transport, the old paths are kept
+pub use tcp_transport::{loopbytes, streamparser, BlockInfo};
@@ -2,15 +2,24 @@
 extern crate log;
 use std::net::ToSocketAddrs;
 
-use std::fs::File;
 use std::io::prelude::*;
-use std::path::Path;
 use std::error::Error;
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
 
-use mio::net::TcpStream;
+use nix::sys::{socket, socket::sockopt::TcpCongestion};
+use serde::Serialize;
+use tokio::net::TcpStream;
 
-use dtp_utils::get_current_usec;
-use streamparser::StreamParser;
+use dtp_utils::{get_current_usec, HEADER_SIZE, MAX_HEADER_SIZE};
+use dtp_utils::pcapng::{Direction, PcapWriter};
+use dtp_utils::metrics::{MetricsExporter, MetricsPage, PUBLISH_INTERVAL};
+use dtp_utils::results::{create_output, output_path, write_json, RunMetadata};
+use tcp_client::{qoe, BlockInfo};
+use tcp_client::latency::LatencyHistograms;
+use tcp_client::streamparser::{ParseError, StreamParser};
+use tcp_transport::signals::Signals;
+use tcp_transport::{BlockReceiver, BlockSender};
 
 const TIMEOUT: u64 = 5000;
 
@@ -21,57 +30,80 @@ const USAGE: &str = "Usage:
     Options:
     --wire-version VERSION   The version number to send to the server [default: babababa].
     --dump-packets PATH      Dump the incoming packets as files in the given directory.
+    --trace CONFIG           The dtp config replayed by the server, blocks of it that are not received count as missed in the QoE.
+    --qoe-weights WEIGHTS    Weights of priorities in the QoE like 1:1,2:2,3:3, a higher number is a higher priority, by default weighted by its value plus one.
+    --qoe-alpha ALPHA        Weight of the priority-weighted hit ratio against the on-time byte ratio in the QoE, in [0, 1] [default: 0.9].
+    --output-dir DIR         Directory of log/tcp_client.log and client.csv, created if missing [default: .].
+    --results-json PATH      Write the run metadata, every block and the aggregates as a JSON document, relative to --output-dir.
+    --deadline-buckets MS    Upper bounds of the deadline buckets of the BCT histograms [default: 100,200,500,1000].
+    --histograms PATH        Export the BCT histograms as an HdrHistogram interval log, relative to --output-dir.
+    --metrics-addr ADDR      Serve live metrics in the Prometheus text format on http://ADDR/metrics.
+    --pcap PATH              Write every read() as a record of a pcapng file, relative to --output-dir.
+    --pcap-snaplen BYTES     Bytes of payload kept in a pcapng record, 0 keeps all [default: 128].
     --no-verify              Don't verify server's certificate.
     --cc-algorithm NAME      Set client congestion control algorithm [default: reno].
     -h --help                Show this screen.
 ";
 
-const CLIENT: mio::Token = mio::Token(1);
-
-#[derive(Clone, Debug, Default, Copy)]
-#[repr(C)]
-struct BlockInfo {
-    start_timestamp: u64,
-    end_timestamp: u64,
-    bct: u64,
-    deadline: i32,
-    priority: i32,
-    block_size: i32,
-    id: u64
-}
-
-fn main () -> Result<(), Box<dyn Error>>{
+#[tokio::main(flavor = "current_thread")]
+async fn main () -> Result<(), Box<dyn Error>>{
+    
+    env_logger::builder()
+    .format_timestamp_nanos()
+    .init();
+    
+    let args = docopt::Docopt::new(USAGE)
+    .and_then(|dopt| dopt.parse())
+    .unwrap_or_else(|e| e.exit());
     
-    let path = Path::new("./log/tcp_client.log");
-    let log_path = Path::new("client.csv");
+    let mut run = RunMetadata::new("tcp_client", env!("CARGO_PKG_VERSION"));
+    let output_dir = args.get_str("--output-dir");
+    let path = output_path(output_dir, "log/tcp_client.log");
+    let log_path = output_path(output_dir, "client.csv");
     let display = path.display();
     // Open a file in write-only mode, returns `io::Result<File>`
-    let mut file = match File::create(&path) {
+    let mut file = match create_output(&path) {
         Err(why) => panic!("couldn't create {}: {}", display, why),
         Ok(file) => file,
     };
 
-    let mut log_file = match File::create(&log_path) {
+    let mut log_file = match create_output(&log_path) {
         Err(why) => panic!("couldn't create {}: {}", log_path.display(), why),
         Ok(file) => file,
     };
     
-    let mut buf = [0; 65535];
-    
-    env_logger::builder()
-    .format_timestamp_nanos()
-    .init();
-    
-    let args = docopt::Docopt::new(USAGE)
-    .and_then(|dopt| dopt.parse())
-    .unwrap_or_else(|e| e.exit());
-    
-    let dump_path = if args.get_str("--dump-packets") != "" {
+    let dump_path = if !args.get_str("--dump-packets").is_empty() {
         Some(args.get_str("--dump-packets"))
     } else {
         None
     };
     
+    let qoe_config = qoe::QoeConfig {
+        weights: qoe::QoeConfig::parse_weights(args.get_str("--qoe-weights"))?,
+        alpha: qoe::QoeConfig::parse_alpha(args.get_str("--qoe-alpha"))?,
+    };
+    let expected = match args.get_str("--trace") {
+        "" => None,
+        trace => {
+            run.set_trace(trace)?;
+            Some(dtp_utils::get_dtp_config(trace))
+        },
+    };
+
+    let mut latency = LatencyHistograms::new(LatencyHistograms::parse_buckets(args.get_str("--deadline-buckets"))?);
+
+    let mut metrics = match args.get_str("--metrics-addr") {
+        "" => None,
+        addr => Some(MetricsExporter::bind(addr)?),
+    };
+
+    let mut pcap = match args.get_str("--pcap") {
+        "" => None,
+        pcap_path => Some(PcapWriter::create(&output_path(output_dir, pcap_path), "tcp_client", args.get_str("--pcap-snaplen").parse()?)?),
+    };
+    // headers before this stream offset are already annotated in the pcapng file
+    let mut pcap_noted: u64 = 0;
+    
     let url_string = format!("http://{0}:{1}", args.get_str("ADDR"), args.get_str("PORT"));
     let url = url::Url::parse(&url_string).unwrap();
     
@@ -80,185 +112,333 @@ fn main () -> Result<(), Box<dyn Error>>{
     
     println!("peer_addr = {}", peer_addr);
     let s = format!("peer_addr = {}\n", peer_addr);
-    match file.write_all(s.as_bytes()) {
-        Err(why) => panic!("couldn't write to {}: {}", display, why),
-        _ => (),
+    if let Err(why) = file.write_all(s.as_bytes()) {
+        panic!("couldn't write to {}: {}", display, why);
     }
     
     
-    // Create a TCP socket and register it with the event loop.
-    let mut client_stream = TcpStream::connect(peer_addr)?;
+    // a signal ends the run with the results so far
+    let mut signals = Signals::new()?;
+    let client_stream = tokio::select! {
+        connected = TcpStream::connect(peer_addr) => connected?,
+        signal = signals.recv() => {
+            // nothing is received, there are no results to write
+            println!("{} before a connection. Quiting...", signal);
+            return Ok(());
+        },
+    };
     println!("Connected to the server!");
     println!("local_addr: {:?}", client_stream.local_addr()?);
-    // Setup the event loop.
-    let mut poll = mio::Poll::new()?;
-    let mut events = mio::Events::with_capacity(1024);
-    
-    poll.registry().register(&mut client_stream, mio::Token(1), mio::Interest::READABLE)?;
+    run.peer_addr = Some(peer_addr.to_string());
+    run.local_addr = Some(client_stream.local_addr()?.to_string());
+    // the name is padded with NULs
+    if let Ok(cc) = socket::getsockopt(client_stream.as_raw_fd(), TcpCongestion) {
+        run.cc_algorithm = Some(cc.to_string_lossy().trim_end_matches('\0').to_string());
+    }
+    let mut receiver = BlockReceiver::with_capacity(client_stream, 65535);
     
     let mut pkt_count = 0;
-    let s =
-        format!("test begin!\n\nBlockID\tbct\tBlockSize\tPriority\tDeadline\n");
-    match file.write_all(s.as_bytes()) {
-        Err(why) => panic!("couldn't write to {}: {}", display, why),
-        _ => (),
+    let s = "test begin!\n\nBlockID\tbct\tBlockSize\tPriority\tDeadline\n";
+    if let Err(why) = file.write_all(s.as_bytes()) {
+        panic!("couldn't write to {}: {}", display, why);
     }
-    let s =
-        format!("block_id,bct,size,priority,deadline,duration\n");
-    match log_file.write_all(s.as_bytes()) {
-        Err(why) => panic!("couldn't write to {}: {}", display, why),
-        _ => (),
+    let s = "block_id,bct,size,priority,deadline,duration\n";
+    if let Err(why) = log_file.write_all(s.as_bytes()) {
+        panic!("couldn't write to {}: {}", log_path.display(), why);
     }
     let start_timestamp = std::time::Instant::now();
-    let mut total_bytes: u64 = 0;
     let mut block_vec: Vec<BlockInfo> = Vec::new();
-    let mut parser = StreamParser::new(65535);
-    'outer: loop {
-        poll.poll(&mut events, Some(std::time::Duration::from_millis(TIMEOUT)))?;
-        
-        if events.is_empty() {
-            // TIMEOUT
-            println!("Client TIMEOUT. Quiting...");
-            let mut good_bytes: u64 = 0;
-            for block in block_vec.iter() {
-                if block.bct < block.deadline as u64 {
-                    good_bytes += block.block_size as u64;
+    let mut parse_errors: u64 = 0;
+    // blocks whose payload does not match the checksum in their header
+    let mut corrupted: Vec<BlockInfo> = Vec::new();
+    // blocks that ended before they started by the local clock, their bct is unknown
+    let mut skewed: Vec<BlockInfo> = Vec::new();
+    // the last blocks before a stall or the end are published too
+    let mut tick = tokio::time::interval(PUBLISH_INTERVAL);
+    let mut read_deadline = tokio::time::Instant::now() + Duration::from_millis(TIMEOUT);
+    loop {
+        // every read takes all the blocks out of the parser, so it has room
+        let read = tokio::select! {
+            read = tokio::time::timeout_at(read_deadline, receiver.read()) => read,
+            _ = tick.tick(), if metrics.is_some() => {
+                if let Some(ref mut metrics) = metrics {
+                    let (total_bytes, parser) = (receiver.received(), receiver.parser());
+                    metrics.publish_with(|| client_metrics(&block_vec, corrupted.len(), skewed.len(), total_bytes, parse_errors, parser, &latency));
+                }
+                continue;
+            },
+            signal = signals.recv() => {
+                println!("{}. Quiting...", signal);
+                run.interrupted = Some(signal.to_string());
+                // the server stops sending
+                if let Err(e) = BlockSender::new(receiver.get_mut()).abort().await {
+                    eprintln!("couldn't send the abort message: {}", e);
                 }
+                break;
+            },
+        };
+        let len = match read {
+            Err(_) => {
+                println!("Client TIMEOUT. Quiting...");
+                break;
+            },
+            // the server side has closed the stream or the writing is done
+            Ok(Ok(0)) => break,
+            Ok(Ok(len)) => {
+                read_deadline = tokio::time::Instant::now() + Duration::from_millis(TIMEOUT);
+                len
+            },
+            // the server was killed, the blocks so far are still written
+            Ok(Err(e)) => {
+                println!("recv() failed: {:?}. Quiting...", e);
+                run.interrupted = Some(format!("recv error: {}", e));
+                break;
+            },
+        };
+        let total_bytes = receiver.received();
+        let parser = receiver.parser_mut();
+        
+        debug!("got {} bytes", len);
+        if let Some(target_path) = dump_path {
+            let path = format!("{}/{}.pkt", target_path, pkt_count);
+            pkt_count += 1;
+            
+            if let Ok(f) = std::fs::File::create(&path) {
+                let mut f = std::io::BufWriter::new(f);
+                let (first, second) = parser.last_received(len);
+                f.write_all(first).ok();
+                f.write_all(second).ok();
             }
-
-            let mut complete_bytes: u64 = 0;
-            for block in block_vec.iter() {
-                complete_bytes += block.block_size as u64;
+        }
+        
+        // the received bytes are gone once they are parsed
+        let chunk = if pcap.is_some() {
+            let (first, second) = parser.last_received(len);
+            [first, second].concat()
+        } else {
+            Vec::new()
+        };
+        
+        let mut blocks: Vec<BlockInfo> = Vec::new();
+        for res in parser.consume() {
+            match res {
+                Ok(block) => blocks.push(block),
+                Err(ParseError::ClockSkew(block)) => {
+                    warn!("{}", ParseError::ClockSkew(block));
+                    skewed.push(block);
+                },
+                Err(ParseError::Corrupted { block, actual }) => {
+                    error!("{}", ParseError::Corrupted { block, actual });
+                    corrupted.push(block);
+                },
+                Err(e) => {
+                    parse_errors += 1;
+                    warn!("{}", e);
+                },
+            }
+        }
+        
+        for block in blocks.iter() {
+            latency.record(block);
+            // Log into client.log
+            // BlockID bct BlockSize Priority Deadline
+            let s = format!("{:<10}\t{:10}\t{:10}\t{:10}\t{:10}\n", 
+                block.id, 
+                block.bct, 
+                block.block_size, 
+                block.priority, 
+                block.deadline
+            );
+            if let Err(why) = file.write_all(s.as_bytes()) {
+                panic!("couldn't write to {}: {}", display, why);
             }
 
-            let s =
-                format!("connection closed, recv=-1 sent=-1 lost=-1 rtt=-1 cwnd=-1, total_bytes={}, complete_bytes={}, good_bytes={}, total_time={}\n", 
-                    total_bytes, 
-                    complete_bytes,
-                    good_bytes,
-                    start_timestamp.elapsed().as_micros()
-                );
-                match file.write_all(s.as_bytes()) {
-                    Err(why) => panic!("couldn't write to {}: {}", display, why),
-                    _ => (),
-                }
+            let s = format!("{},{},{},{},{},{}\n", 
+                block.id, 
+                block.bct, 
+                block.block_size, 
+                block.priority, 
+                block.deadline,
+                start_timestamp.elapsed().as_micros()
+            );
+            if let Err(why) = log_file.write_all(s.as_bytes()) {
+                panic!("couldn't write to {}: {}", log_path.display(), why);
+            }
+        }
+        if let Some(ref mut pcap) = pcap {
+            let end = total_bytes;
+            let comments = pcap_comments(end - len as u64, end, &blocks, parser.current_block(), &mut pcap_noted);
+            if let Err(why) = pcap.write_packet(get_current_usec(), Direction::Inbound, &chunk, &comments) {
+                panic!("couldn't write to {}: {}", args.get_str("--pcap"), why);
+            }
+        }
+        block_vec.append(&mut blocks);
+        if let Some(ref mut metrics) = metrics {
+            metrics.publish_with(|| client_metrics(&block_vec, corrupted.len(), skewed.len(), total_bytes, parse_errors, parser, &latency));
+        }
+        if parser.aborted().is_some() {
+            println!("The server aborted the run. Quiting...");
+            run.interrupted = Some("server abort".to_string());
             break;
         }
-    
-        for event in events.iter() {
-            match event.token() {
-                CLIENT => {
-                    if event.is_readable() {
-                        let mut connection_closed = false;
-                        'recv: loop {
-                            let len = match client_stream.read(&mut buf) {
-                                Ok(0) => {
-                                    // Reading 0 bytes means the server side has closed the stream
-                                    // or the writing is done
-                                    connection_closed = true;
-                                    break;
-                                }
-                                Ok(v) => v,
-                                Err(e) => {
-                                    if e.kind() == std::io::ErrorKind::WouldBlock {
-                                        debug!("recv() would block");
-                                        break 'recv;
-                                    }
-                                    panic!("recv() failed: {:?}", e);
-                                },
-                            };
-                            
-                            debug!("got {} bytes", len);
-                            total_bytes += len as u64;
-                            if len != 0 {
-                                if let Some(target_path) = dump_path {
-                                    let path = format!("{}/{}.pkt", target_path, pkt_count);
-                                    pkt_count += 1;
-                                    
-                                    if let Ok(f) = std::fs::File::create(&path) {
-                                        let mut f = std::io::BufWriter::new(f);
-                                        f.write_all(&buf[..len]).ok();
-                                    }
-                                }
-                                
-                                let mut total_size = 0;
-                                let mut blocks: Vec<BlockInfo> = Vec::new();
-                                
-                                while total_size < len {
-                                    total_size += parser.recv(&buf[total_size..len], len - total_size);
-                                    blocks.append(&mut parser.consume());
-                                }
-                                
-                                for block in blocks.iter() {
-                                    // Log into client.log
-                                    // BlockID bct BlockSize Priority Deadline
-                                    let s = format!("{:<10}\t{:10}\t{:10}\t{:10}\t{:10}\n", 
-                                        block.id, 
-                                        block.bct, 
-                                        block.block_size, 
-                                        block.priority, 
-                                        block.deadline
-                                    );
-                                    match file.write_all(s.as_bytes()) {
-                                        Err(why) =>
-                                        panic!("couldn't write to {}: {}", display, why),
-                                        _ => (),
-                                    }
+    }
+    log_file.flush()?;
+    let total_bytes = receiver.received();
+    let parser = receiver.parser();
+    let total_time = start_timestamp.elapsed().as_micros();
+    run.end_time = get_current_usec();
+    if let Some(ref mut metrics) = metrics {
+        metrics.publish(client_metrics(&block_vec, corrupted.len(), skewed.len(), total_bytes, parse_errors, parser, &latency));
+    }
+    if let Some(ref mut pcap) = pcap {
+        pcap.flush()?;
+    }
 
-                                    let s = format!("{},{},{},{},{},{}\n", 
-                                        block.id, 
-                                        block.bct, 
-                                        block.block_size, 
-                                        block.priority, 
-                                        block.deadline,
-                                        start_timestamp.elapsed().as_micros()
-                                    );
-                                    match log_file.write_all(s.as_bytes()) {
-                                        Err(why) =>
-                                        panic!("couldn't write to {}: {}", log_path.display(), why),
-                                        _ => (),
-                                    }
-                                }
-                                block_vec.append(&mut blocks);
-                            } 
-                        }
-                        if connection_closed {
-                            let mut good_bytes: u64 = 0;
-                            for block in block_vec.iter() {
-                                if block.bct < block.deadline as u64 {
-                                    good_bytes += block.block_size as u64;
-                                }
-                            }
+    let qoe = qoe::score(&block_vec, expected.as_deref(), total_time as u64, &qoe_config);
+    println!("qoe={:.6}, weighted_hit_ratio={:.6}, on_time_byte_ratio={:.6}, on_time_blocks={}/{}",
+        qoe.qoe, qoe.weighted_hit_ratio, qoe.on_time_byte_ratio, qoe.on_time_blocks, qoe.blocks);
+    println!("priority\tblocks\ton_time\thit_ratio\tgoodput(B/s)");
+    for (priority, stats) in qoe.per_priority.iter() {
+        println!("{}\t{}\t{}\t{:.6}\t{:.1}", priority, stats.blocks, stats.on_time_blocks, stats.hit_ratio, stats.goodput);
+    }
+    print!("{}", latency.table());
 
-                            let mut complete_bytes: u64 = 0;
-                            for block in block_vec.iter() {
-                                complete_bytes += block.block_size as u64;
-                            }
+    let stats = Stats::new(&block_vec, corrupted.len() as u64, skewed.len() as u64, total_bytes, total_time as u64, parse_errors);
+    if stats.verified_blocks + stats.corrupted_blocks > 0 {
+        println!("checksum: verified_blocks={}, corrupted_blocks={}", stats.verified_blocks, stats.corrupted_blocks);
+        for block in corrupted.iter() {
+            println!("corrupted block {} at {} (size {})", block.id, block.offset, block.block_size);
+        }
+    }
+    if stats.clock_skew_blocks > 0 {
+        println!("clock skew: {} blocks ended before they started, they are not counted as received", stats.clock_skew_blocks);
+    }
+    let s = summary(&stats, qoe.qoe, run.interrupted.as_deref());
+    if let Err(why) = file.write_all(s.as_bytes()) {
+        panic!("couldn't write to {}: {}", display, why);
+    }
+    if !args.get_str("--results-json").is_empty() {
+        let results = serde_json::json!({
+            "run": run,
+            "blocks": block_vec,
+            "corrupted": corrupted,
+            "clock_skew": skewed,
+            "stats": stats,
+            "qoe": qoe,
+            "latency": latency.quantiles(),
+        });
+        write_json(&output_path(output_dir, args.get_str("--results-json")), &results)?;
+    }
+    if !args.get_str("--histograms").is_empty() {
+        let mut f = std::io::BufWriter::new(create_output(&output_path(output_dir, args.get_str("--histograms")))?);
+        latency.write_log(&mut f, run.start_time, total_time as u64)?;
+        f.flush()?;
+    }
+    Ok(())
+}
 
-                            let s =
-                                format!("connection closed, recv=-1 sent=-1 lost=-1 rtt=-1 cwnd=-1, total_bytes={}, complete_bytes={}, good_bytes={}, total_time={}\n", 
-                                    total_bytes, 
-                                    complete_bytes,
-                                    good_bytes,
-                                    start_timestamp.elapsed().as_micros()
-                                );
-                            match file.write_all(s.as_bytes()) {
-                                Err(why) => panic!("couldn't write to {}: {}", display, why),
-                                _ => (),
-                            }
-                            break 'outer;
-                        }
-                    }
-                    if event.is_writable() {
-                        panic!("writeable event");
-                    }
-                },
-                _ => unreachable!()
+// the page served on --metrics-addr
+fn client_metrics(block_vec: &[BlockInfo], corrupted: usize, skewed: usize, total_bytes: u64, parse_errors: u64, parser: &StreamParser, latency: &LatencyHistograms) -> String {
+    let misses = block_vec.iter().filter(|block| block.bct >= block.deadline as u64).count();
+    let partial = match parser.current_block() {
+        Some(block) => (block.block_size - parser.block_remaining()) as f64,
+        None => 0.0,
+    };
+    let mut page = MetricsPage::new();
+    page.single("dtp_client_bytes_received_total", "counter", "Bytes read from the socket.", total_bytes as f64)
+        .single("dtp_client_blocks_received_total", "counter", "Blocks received completely.", block_vec.len() as f64)
+        .single("dtp_client_deadline_misses_total", "counter", "Blocks received after their deadline.", misses as f64)
+        .single("dtp_client_parse_errors_total", "counter", "Errors of the stream parser.", parse_errors as f64)
+        .single("dtp_client_corrupted_blocks_total", "counter", "Blocks whose payload does not match their checksum.", corrupted as f64)
+        .single("dtp_client_clock_skew_blocks_total", "counter", "Blocks that ended before they started by the local clock.", skewed as f64)
+        .single("dtp_client_partial_block_bytes", "gauge", "Payload received of the block in progress.", partial)
+        .family("dtp_client_bct_seconds", "summary", "BCT of the received blocks by priority and deadline bucket.");
+    for (class, h) in latency.iter() {
+        let class = class.to_string();
+        for quantile in ["0.5", "0.9", "0.99"].iter() {
+            let value = h.value_at_quantile(quantile.parse().unwrap()) as f64 / 1e6;
+            page.sample("dtp_client_bct_seconds", &[("class", &class), ("quantile", quantile)], value);
+        }
+        page.sample("dtp_client_bct_seconds_count", &[("class", &class)], h.len() as f64);
+    }
+    page.render()
+}
+
+// block boundaries in the stream bytes [start, end) as pcapng comments
+fn pcap_comments(start: u64, end: u64, blocks: &[BlockInfo], current: Option<&BlockInfo>, noted: &mut u64) -> Vec<String> {
+    let mut comments = vec![format!("bytes {}-{}", start, end)];
+    for block in blocks.iter().chain(current) {
+        // a header split over several reads is annotated in the read that completes it
+        if block.offset >= *noted {
+            comments.push(format!("block {} header at {} (size {}, priority {}, deadline {})",
+                block.id, block.offset, block.block_size, block.priority, block.deadline));
+            *noted = block.offset + 1;
+        }
+    }
+    for block in blocks.iter() {
+        let hdr_len = if block.checksum.is_some() { MAX_HEADER_SIZE } else { HEADER_SIZE };
+        comments.push(format!("block {} ends at {}", block.id, block.offset + (hdr_len as u64) + block.block_size));
+    }
+    comments
+}
+
+/// Aggregates of a run, the `stats` object of the results JSON
+#[derive(Debug, Default, Serialize)]
+struct Stats {
+    /// bytes read from the socket
+    total_bytes: u64,
+    /// payload of the received blocks
+    complete_bytes: u64,
+    /// payload of the blocks received before their deadline
+    good_bytes: u64,
+    blocks: u64,
+    on_time_blocks: u64,
+    parse_errors: u64,
+    /// received blocks whose checksum matches
+    verified_blocks: u64,
+    /// blocks whose checksum does not match, they are not in `blocks`
+    corrupted_blocks: u64,
+    /// blocks that ended before they started by the local clock, they are
+    /// not in `blocks` as their bct is unknown
+    clock_skew_blocks: u64,
+    /// us
+    total_time: u64,
+}
+
+impl Stats {
+    fn new(block_vec: &[BlockInfo], corrupted_blocks: u64, clock_skew_blocks: u64, total_bytes: u64, total_time: u64, parse_errors: u64) -> Self {
+        let mut stats = Stats {
+            total_bytes,
+            total_time,
+            parse_errors,
+            verified_blocks: block_vec.iter().filter(|block| block.checksum.is_some()).count() as u64,
+            corrupted_blocks,
+            clock_skew_blocks,
+            blocks: block_vec.len() as u64,
+            ..Stats::default()
+        };
+        for block in block_vec.iter() {
+            stats.complete_bytes += block.block_size;
+            if block.bct < block.deadline as u64 {
+                stats.good_bytes += block.block_size;
+                stats.on_time_blocks += 1;
             }
         }
+        stats
     }
-    Ok(())
 }
 
-mod loopbytes;
-mod streamparser;
\ No newline at end of file
+/// The last line of client.log, `interrupted` tells why the run stopped early
+fn summary(stats: &Stats, qoe: f64, interrupted: Option<&str>) -> String {
+    let mut s = format!("connection closed, recv=-1 sent=-1 lost=-1 rtt=-1 cwnd=-1, total_bytes={}, complete_bytes={}, good_bytes={}, total_time={}, qoe={:.6}", 
+        stats.total_bytes, 
+        stats.complete_bytes,
+        stats.good_bytes,
+        stats.total_time,
+        qoe
+    );
+    if let Some(why) = interrupted {
+        s += &format!(", interrupted={}", why);
+    }
+    s + "\n"
+}
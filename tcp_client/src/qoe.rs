@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use dtp_utils::dtp_config;
+
+use crate::BlockInfo;
+
+/// How blocks are weighted in the QoE score
+///
+/// ```text
+/// weighted_hit_ratio = sum(weight(priority) of blocks with bct < deadline) / sum(weight(priority) of all blocks)
+/// on_time_byte_ratio = bytes of blocks with bct < deadline / bytes of all blocks
+/// qoe = alpha * weighted_hit_ratio + (1 - alpha) * on_time_byte_ratio
+/// ```
+///
+/// A higher number means a higher priority, as `dtp_config.h` documents and
+/// `QueueDriver::by_priority` sends. The demo solution in
+/// `tcp_server/demo/solution.cxx` treats a lower number as more urgent instead,
+/// its scores need explicit weights. A priority without an explicit weight is
+/// weighted by its value plus one, so that priority 0 still counts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QoeConfig {
+    pub weights: BTreeMap<i32, f64>,
+    pub alpha: f64,
+}
+
+impl Default for QoeConfig {
+    fn default() -> Self {
+        QoeConfig {
+            weights: BTreeMap::new(),
+            alpha: 0.9,
+        }
+    }
+}
+
+impl QoeConfig {
+    /// Parse weights like `1:1,2:2.5,3:4`
+    pub fn parse_weights(s: &str) -> Result<BTreeMap<i32, f64>, String> {
+        let mut weights = BTreeMap::new();
+        for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            let mut parts = item.splitn(2, ':');
+            let priority = parts.next().unwrap_or("").trim().parse::<i32>();
+            let weight = parts.next().unwrap_or("").trim().parse::<f64>();
+            match (priority, weight) {
+                (Ok(priority), Ok(weight)) if weight >= 0.0 => {
+                    weights.insert(priority, weight);
+                }
+                _ => return Err(format!("invalid priority weight {:?}, expect PRIORITY:WEIGHT", item)),
+            }
+        }
+        Ok(weights)
+    }
+
+    /// Parse an alpha in `[0, 1]`
+    pub fn parse_alpha(s: &str) -> Result<f64, String> {
+        match s.trim().parse::<f64>() {
+            Ok(alpha) if (0.0..=1.0).contains(&alpha) => Ok(alpha),
+            _ => Err(format!("invalid QoE alpha {:?}, expect a number in [0, 1]", s)),
+        }
+    }
+
+    pub fn weight(&self, priority: i32) -> f64 {
+        match self.weights.get(&priority) {
+            Some(weight) => *weight,
+            // priorities are not negative in a valid trace
+            None => priority.max(0) as f64 + 1.0,
+        }
+    }
+}
+
+/// Results of the blocks of one priority
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PriorityStats {
+    pub blocks: u64,
+    pub on_time_blocks: u64,
+    pub bytes: u64,
+    pub on_time_bytes: u64,
+    pub hit_ratio: f64,
+    /// on time bytes per second of the whole run
+    pub goodput: f64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct QoeScore {
+    /// blocks the score is computed over, received or not
+    pub blocks: u64,
+    pub received_blocks: u64,
+    pub on_time_blocks: u64,
+    pub weighted_hit_ratio: f64,
+    pub on_time_byte_ratio: f64,
+    pub alpha: f64,
+    pub qoe: f64,
+    pub per_priority: BTreeMap<i32, PriorityStats>,
+}
+
+fn ratio(a: f64, b: f64) -> f64 {
+    if b > 0.0 {
+        a / b
+    } else {
+        0.0
+    }
+}
+
+/// Score the received blocks of a run that lasted `total_time` us
+///
+/// The blocks in `expected`, the trace replayed by the server, that are not
+/// received count as missed. Without it only the received blocks are scored.
+pub fn score(blocks: &[BlockInfo], expected: Option<&[dtp_config]>, total_time: u64, config: &QoeConfig) -> QoeScore {
+    let mut per_priority: BTreeMap<i32, PriorityStats> = BTreeMap::new();
+    for block in blocks {
+        let stats = per_priority.entry(block.priority).or_default();
+        stats.blocks += 1;
+        stats.bytes += block.block_size;
+        if block.bct < block.deadline as u64 {
+            stats.on_time_blocks += 1;
+            stats.on_time_bytes += block.block_size;
+        }
+    }
+    if let Some(cfgs) = expected {
+        let mut trace_stats: BTreeMap<i32, (u64, u64)> = BTreeMap::new();
+        for cfg in cfgs {
+            let entry = trace_stats.entry(cfg.priority).or_default();
+            entry.0 += 1;
+            entry.1 += cfg.block_size;
+        }
+        for (priority, (count, bytes)) in trace_stats {
+            let stats = per_priority.entry(priority).or_default();
+            stats.blocks = stats.blocks.max(count);
+            stats.bytes = stats.bytes.max(bytes);
+        }
+    }
+
+    let seconds = total_time as f64 / 1_000_000.0;
+    let mut score = QoeScore {
+        received_blocks: blocks.len() as u64,
+        alpha: config.alpha,
+        ..QoeScore::default()
+    };
+    let (mut weight_sum, mut on_time_weight) = (0.0, 0.0);
+    let (mut bytes, mut on_time_bytes) = (0, 0);
+    for (priority, stats) in per_priority.iter_mut() {
+        stats.hit_ratio = ratio(stats.on_time_blocks as f64, stats.blocks as f64);
+        stats.goodput = ratio(stats.on_time_bytes as f64, seconds);
+        let weight = config.weight(*priority);
+        weight_sum += weight * stats.blocks as f64;
+        on_time_weight += weight * stats.on_time_blocks as f64;
+        bytes += stats.bytes;
+        on_time_bytes += stats.on_time_bytes;
+        score.blocks += stats.blocks;
+        score.on_time_blocks += stats.on_time_blocks;
+    }
+    score.weighted_hit_ratio = ratio(on_time_weight, weight_sum);
+    score.on_time_byte_ratio = ratio(on_time_bytes as f64, bytes as f64);
+    score.qoe = config.alpha * score.weighted_hit_ratio + (1.0 - config.alpha) * score.on_time_byte_ratio;
+    score.per_priority = per_priority;
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(priority: i32, block_size: u64, bct: u64) -> BlockInfo {
+        BlockInfo {
+            priority,
+            block_size,
+            bct,
+            deadline: 200,
+            ..BlockInfo::default()
+        }
+    }
+
+    #[test]
+    fn parse_weights() {
+        let weights = QoeConfig::parse_weights("1:1, 2:2.5,3:4").unwrap();
+        assert_eq!(Some(&2.5), weights.get(&2));
+        assert_eq!(3, weights.len());
+        assert!(QoeConfig::parse_weights("1=1").is_err());
+        assert!(QoeConfig::parse_weights("1:-1").is_err());
+        assert!(QoeConfig::parse_weights("").unwrap().is_empty());
+        assert_eq!(Ok(0.5), QoeConfig::parse_alpha("0.5"));
+        assert_eq!(Ok(1.0), QoeConfig::parse_alpha("1"));
+        assert!(QoeConfig::parse_alpha("1.5").is_err());
+        assert!(QoeConfig::parse_alpha("-0.1").is_err());
+        assert!(QoeConfig::parse_alpha("NaN").is_err());
+    }
+
+    #[test]
+    fn weighted() {
+        let blocks = vec![block(1, 100, 10), block(1, 100, 300), block(2, 1000, 100), block(2, 1000, 200)];
+        let config = QoeConfig {
+            weights: QoeConfig::parse_weights("2:3").unwrap(),
+            alpha: 0.5,
+        };
+        let score = score(&blocks, None, 2_000_000, &config);
+        assert_eq!(4, score.blocks);
+        assert_eq!(2, score.on_time_blocks);
+        // (1 + 3) / (1 + 1 + 3 + 3)
+        assert!((score.weighted_hit_ratio - 0.5).abs() < 1e-9);
+        assert!((score.on_time_byte_ratio - 1100.0 / 2200.0).abs() < 1e-9);
+        assert!((score.qoe - 0.5).abs() < 1e-9);
+        let p2 = &score.per_priority[&2];
+        assert_eq!(1000, p2.on_time_bytes);
+        assert!((p2.goodput - 500.0).abs() < 1e-9);
+        assert!((p2.hit_ratio - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn missing_blocks() {
+        let cfg = |priority, block_size| dtp_config { deadline: 200, priority, block_size, send_time_gap: 0.0 };
+        let expected = vec![cfg(1, 100), cfg(1, 100), cfg(3, 50), cfg(0, 50)];
+        let blocks = vec![block(1, 100, 10), block(0, 50, 10)];
+        let score = score(&blocks, Some(&expected), 1_000_000, &QoeConfig::default());
+        assert_eq!(4, score.blocks);
+        assert_eq!(2, score.received_blocks);
+        // weights are the priorities plus one: (2 + 1) / (2 + 2 + 4 + 1)
+        assert!((score.weighted_hit_ratio - 3.0 / 9.0).abs() < 1e-9);
+        assert!((score.on_time_byte_ratio - 150.0 / 300.0).abs() < 1e-9);
+        assert_eq!(0, score.per_priority[&3].on_time_blocks);
+    }
+}
@@ -1,52 +1,129 @@
-#[macro_use]
-extern crate log;
-
-use std::net;
 use std::net::SocketAddr;
 use std::io::prelude::*;
-use std::time::{Duration, Instant};
-use std::collections::HashMap;
+use std::time::Duration;
 use std::error::Error;
-use std::sync::Arc;
 use std::{io};
+use std::cell::{Cell, RefCell};
 
-use ring::rand::*;
 
 use dtp_utils::*;
+use dtp_utils::pcapng::{Direction, PcapWriter};
+use dtp_utils::metrics::{send_queue_bytes, MetricsExporter, MetricsPage, PUBLISH_INTERVAL};
+use dtp_utils::results::{create_output, output_path, write_json, RunMetadata};
+
+use serde::Serialize;
 
-use std::fs::File;
-use std::path::Path;
+use tcp_transport::payload::Payload;
+use tcp_transport::queue::QueueEvent;
+use tcp_transport::replay::{block_index, replay};
+use tcp_transport::schedule::Schedule;
+use tcp_transport::signals::Signals;
+use tcp_transport::{block_queue, BlockReceiver, BlockSender};
 
-use mio::{Token, Poll, event::*, Interest};
-use mio::net::{TcpListener, TcpStream};
+
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpListener;
 
 use nix::sys::{socket, socket::sockopt::TcpCongestion};
 use std::{os::unix::io::AsRawFd, ffi::OsString};
 
-macro_rules! log {
-    ($file:expr, $display:expr, $($arg:tt)*) => {
-        let s = format!($($arg)*);
-        match $file.write_all(s.as_bytes()) {
-            Err(why) => panic!("couldn't write to {}: {}", $display, why),
-            _ => (),
-        };
-    }
-}
-
 const USAGE: &str = "Usage:
 server [options] ADDR PORT CONFIG
 server -h | --help
 
 Options:
+--cc-algorithm NAME      Congestion control algorithm of the connection [default: reno].
+--loop N                 Replay the trace N times, or forever [default: 1].
+--time-scale X           Multiply the send time gaps by X, 0.5 replays twice as fast, 0 sends at once (not with --loop forever) [default: 1].
+--duration SECS          Send no block from SECS after the connection on.
+--payload SOURCE         Bytes after the block headers: zeros, random, file:PATH (repeated) or dir:PATH (block i of the trace is file i, in name order) [default: zeros].
+--checksum               Put the CRC-32C of the payload in every block header, the client verifies it.
+--scheduler POLICY       Which block waiting for the socket is sent next: fifo in send time order, priority the highest priority value first [default: fifo].
+--drop-expired           Drop the blocks whose deadline passed while they waited for the socket.
+--output-dir DIR         Directory of server.csv, one line per sent block, created if missing [default: .].
+--results-json PATH      Write the run metadata, every sent block and the aggregates as a JSON document, relative to --output-dir.
+--metrics-addr ADDR      Serve live metrics in the Prometheus text format on http://ADDR/metrics.
+--pcap PATH              Write every write() as a record of a pcapng file, relative to --output-dir.
+--pcap-snaplen BYTES     Bytes of payload kept in a pcapng record, 0 keeps all [default: 128].
 -h --help                Show this screen.
 ";
 
 
 const TIMEOUT: u64 = 50000;
-const MAX_BLOCK_SIZE: usize = 1000000;
-static mut DATA_BUF: [u8; MAX_BLOCK_SIZE + 10000] = [0; MAX_BLOCK_SIZE + 10000];
 
-fn main() -> Result<(), Box<dyn Error>> {
+/// A block written to the socket, times are unix time in us
+#[derive(Debug, Serialize)]
+struct SentBlock {
+    id: u64,
+    /// of the trace, from 0
+    iteration: u64,
+    block_size: u64,
+    priority: u64,
+    deadline: u64,
+    /// `start + gap_sum[i]`
+    scheduled: u64,
+    /// the first byte is handed to the socket
+    first_write: u64,
+    /// the last byte is written
+    last_write: u64,
+    /// `WouldBlock` errors while writing the block
+    would_block: u64,
+}
+
+impl SentBlock {
+    const CSV_HEADER: &'static str = "block_id,size,priority,deadline,scheduled,first_write,last_write,queue_time,send_time,would_block,iteration\n";
+
+    /// a line of server.csv, `queue_time` is from `scheduled` to `first_write`
+    /// and `send_time` from `first_write` to `last_write`
+    fn csv(&self) -> String {
+        format!("{},{},{},{},{},{},{},{},{},{},{}\n",
+            self.id,
+            self.block_size,
+            self.priority,
+            self.deadline,
+            self.scheduled,
+            self.first_write,
+            self.last_write,
+            self.first_write.saturating_sub(self.scheduled),
+            self.last_write - self.first_write,
+            self.would_block,
+            self.iteration
+        )
+    }
+}
+
+/// Aggregates of one iteration of the trace
+#[derive(Debug, Serialize)]
+struct IterationStats {
+    iteration: u64,
+    sent_blocks: u64,
+    total_bytes: u64,
+    /// us from the scheduled time of the first block to the last write
+    total_time: u64,
+    throughput: f64,
+}
+
+fn iteration_stats(sent_blocks: &[SentBlock]) -> Vec<IterationStats> {
+    let mut stats: Vec<IterationStats> = Vec::new();
+    let mut start = 0;
+    for block in sent_blocks {
+        if stats.last().is_none_or(|last| last.iteration != block.iteration) {
+            start = block.scheduled;
+            stats.push(IterationStats { iteration: block.iteration, sent_blocks: 0, total_bytes: 0, total_time: 0, throughput: 0.0 });
+        }
+        let last = stats.last_mut().unwrap();
+        last.sent_blocks += 1;
+        last.total_bytes += block.block_size;
+        last.total_time = block.last_write.saturating_sub(start);
+        if last.total_time > 0 {
+            last.throughput = last.total_bytes as f64 / (last.total_time as f64 / 1000.0 / 1000.0);
+        }
+    }
+    stats
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn Error>> {
     let args = docopt::Docopt::new(USAGE)
     .and_then(|dopt| dopt.parse())
     .unwrap_or_else(|e| e.exit());
@@ -64,229 +141,312 @@ fn main() -> Result<(), Box<dyn Error>> {
     let socket_addr = peer_addr.parse::<SocketAddr>()?;
     // load dtp configs
     let config_file = args.get_str("CONFIG");
+    let mut run = RunMetadata::new("tcp_server", env!("CARGO_PKG_VERSION"));
+    run.set_trace(config_file)?;
     let cfgs = get_dtp_config(config_file);
-    if cfgs.len() <= 0 {
+    if cfgs.is_empty() {
         eprintln!("Error dtp config length: 0");
         panic!("Error: No dpt config is found");
     }
     
     // println!("socket_addr: {:?}", socket_addr);
     // create TCP listener
-    let mut tcp_server = TcpListener::bind(socket_addr)?;
+    let tcp_server = TcpListener::bind(socket_addr).await?;
     if let Ok(val) = socket::getsockopt::<TcpCongestion>(tcp_server.as_raw_fd(), TcpCongestion) {
         println!("{:?}", val);
     }
     
-    match socket::setsockopt(tcp_server.as_raw_fd(), TcpCongestion, &OsString::from("reno")) {
-        Ok(()) => println!("set cc to reno"),
-        Err(e) => println!("setsockopt err {:?}", e),
+    let cc_algorithm = args.get_str("--cc-algorithm");
+    match socket::setsockopt(tcp_server.as_raw_fd(), TcpCongestion, &OsString::from(cc_algorithm)) {
+        Ok(()) => println!("set cc to {}", cc_algorithm),
+        Err(e) => {
+            // results of another algorithm would be mistaken for this one
+            eprintln!("setsockopt err {:?}, is {} in net.ipv4.tcp_allowed_congestion_control?", e, cc_algorithm);
+            return Err(e.into());
+        }
     }
     
     if let Ok(val) = socket::getsockopt::<TcpCongestion>(tcp_server.as_raw_fd(), TcpCongestion) {
         println!("{:?}", val);
     }
     
-    let mut poll = Poll::new()?;
-    
-    const SERVER: Token = Token(0);
-    const CLIENT: Token = Token(1);
-    poll.registry().register(&mut tcp_server, SERVER, Interest::READABLE)?;
+    let mut client_stream: Option<OwnedWriteHalf> = None;
     
-    let mut events = Events::with_capacity(1024);
-    let mut amount = 0;
-    let mut client_stream: Option<TcpStream> = None;
-    
-    let mut gap_sum: Vec<u64> = vec![0; cfgs.len()]; // us
-    gap_sum[0] = (cfgs[0].send_time_gap * 1_000_000.0) as u64;
-    for i in 1..cfgs.len() {
-        gap_sum[i] = (cfgs[i].send_time_gap * 1_000_000.0) as u64 + gap_sum[i - 1];
+    let loops = match args.get_str("--loop") {
+        "forever" => None,
+        loops => Some(loops.parse()?),
+    };
+    let duration = match args.get_str("--duration") {
+        "" => None,
+        secs => Some((secs.parse::<f64>()? * 1_000_000.0) as u64),
+    };
+    let time_scale: f64 = args.get_str("--time-scale").parse()?;
+    if !time_scale.is_finite() || time_scale < 0.0 {
+        return Err(format!("invalid time scale {}", time_scale).into());
+    }
+    // block i is block i % cfgs.len() of iteration i / cfgs.len()
+    let schedule = Schedule::new(&cfgs, time_scale, loops, duration);
+    if loops.is_none() && schedule.period() == 0 {
+        // every iteration would be due at once, forever
+        return Err(format!("--loop forever needs a trace that takes time, the time scale is {}", time_scale).into());
     }
-    
-    let mut send_amount = 0;
     
     let mut start_timestamp: Option<u64> = None;
-    let mut should_send_time: Option<u64> = None;
     
-    let mut total_bytes: u64 = 0;
+    // shared by the events of the driver and the metrics timer
+    let total_bytes = Cell::new(0u64);
+    let sent_blocks: RefCell<Vec<SentBlock>> = RefCell::new(Vec::new());
+    // dropped by --drop-expired
+    let expired_blocks = Cell::new(0usize);
+    let send_log_path = output_path(args.get_str("--output-dir"), "server.csv");
+    let mut send_log = match create_output(&send_log_path) {
+        Err(why) => panic!("couldn't create {}: {}", send_log_path.display(), why),
+        Ok(file) => io::BufWriter::new(file),
+    };
+    if let Err(why) = send_log.write_all(SentBlock::CSV_HEADER.as_bytes()) {
+        panic!("couldn't write to {}: {}", send_log_path.display(), why);
+    }
     
-    let mut total_size : usize= 0;
-    let mut is_timeout = false;
-    'outer: loop {
-        let timeout = 
-            if start_timestamp.is_some() && amount < cfgs.len() {
-                // during sending 
-                is_timeout = false;
-                let c_time = get_current_usec();
-                if start_timestamp.clone().unwrap() + gap_sum[amount] > c_time {
-                    start_timestamp.clone().unwrap() + gap_sum[amount] - c_time
-                } else {
-                    0
+    let mut payload = Payload::parse(args.get_str("--payload"))?;
+    let block_sizes: Vec<u64> = cfgs.iter().map(|cfg| cfg.block_size).collect();
+    match payload.mismatches(&block_sizes) {
+        0 => {},
+        n => eprintln!("warning: {} of {} blocks differ in size from their payload file, it is repeated or cut", n, cfgs.len()),
+    }
+    let checksum = args.get_bool("--checksum");
+    let by_priority = match args.get_str("--scheduler") {
+        "fifo" => false,
+        "priority" => true,
+        other => return Err(format!("unknown scheduler {}, expect fifo or priority", other).into()),
+    };
+    let drop_expired = args.get_bool("--drop-expired");
+    // bytes written to the stream so far
+    let stream_offset = Cell::new(0u64);
+    let mut pcap = match args.get_str("--pcap") {
+        "" => None,
+        pcap_path => Some(PcapWriter::create(&output_path(args.get_str("--output-dir"), pcap_path), "tcp_server", args.get_str("--pcap-snaplen").parse()?)?),
+    };
+    let metrics = RefCell::new(match args.get_str("--metrics-addr") {
+        "" => None,
+        addr => Some(MetricsExporter::bind(addr)?),
+    });
+    // a first signal stops the run after the block being sent, a second one
+    // at once
+    let mut signals = Signals::new()?;
+    let accepted = tokio::select! {
+        accepted = tokio::time::timeout(Duration::from_millis(TIMEOUT), tcp_server.accept()) => Some(accepted),
+        signal = signals.recv() => {
+            eprintln!("{} before a connection. Quiting...", signal);
+            run.interrupted = Some(signal.to_string());
+            None
+        },
+    };
+    match accepted {
+        None => {},
+        Some(Err(_)) => println!("Server, timeout. Quiting..."),
+        Some(Ok(accepted)) => {
+            let (stream, addr) = accepted?;
+            run.peer_addr = Some(addr.to_string());
+            run.local_addr = Some(stream.local_addr()?.to_string());
+            // the name is padded with NULs
+            if let Ok(cc) = socket::getsockopt(stream.as_raw_fd(), TcpCongestion) {
+                run.cc_algorithm = Some(cc.to_string_lossy().trim_end_matches('\0').to_string());
+            }
+            let fd = stream.as_raw_fd();
+            let start = get_current_usec();
+            start_timestamp = Some(start);
+            eprintln!("new connection, timestamp: {}", start);
+
+            let (read_half, write_half) = stream.into_split();
+            // the client only writes to abort the run
+            let mut receiver = BlockReceiver::new(read_half);
+            let (queue, driver) = block_queue(BlockSender::new(write_half));
+            let mut driver = driver.checksum(checksum).by_priority(by_priority).drop_expired(drop_expired);
+            let abort = queue.abort_handle();
+            let publish = || {
+                if let Some(ref mut metrics) = *metrics.borrow_mut() {
+                    metrics.publish_with(|| server_metrics(&schedule, start_timestamp, sent_blocks.borrow().len(), expired_blocks.get(), total_bytes.get(), stream_offset.get(), send_queue_bytes(fd)));
                 }
-            } else {
-                is_timeout = true;
-                TIMEOUT * 1000
             };
-        poll.poll(&mut events, Some(Duration::from_micros(timeout)))?;
-        
-        if events.is_empty() { 
-            if is_timeout {
-                // timeout
-                println!("Server, timeout. Quiting...");
-                break;
-            } else {
-                amount += 1;
-                debug!("amount in queue: {}", amount);
-            }
-        }
-        
-        // handle events
-        for event in events.iter() {
-            match event.token() {
-                // establish connection and get TcpStream
-                SERVER => {
-                    match tcp_server.accept() {
-                        Ok((mut stream, _addr)) => {
-                            // println!("Got a connection from : {}", addr);
-                            if client_stream.is_none() {
-                                poll.registry().register(&mut stream, CLIENT, Interest::WRITABLE)?;
-                                client_stream = Some(stream);
-                                
-                                let cur_time = get_current_usec();
-                                start_timestamp = Some(cur_time);
-                                if let Some(s_time) = start_timestamp {
-                                    eprintln!("new connection, timestamp: {}", s_time);
+            // the last events before a stall or the end are published too
+            let mut tick = tokio::time::interval(PUBLISH_INTERVAL);
+            let finished = {
+                let sending = async { tokio::join!(replay(queue, &schedule, &mut payload, start), driver.run(|event| {
+                    match event {
+                        QueueEvent::Write(write) => {
+                            if let Some(ref mut pcap) = pcap {
+                                let end = write.offset + write.data.len() as u64;
+                                let mut comments = vec![format!("bytes {}-{}", write.offset, end)];
+                                if write.starts {
+                                    comments.push(format!("block {} header at {} (size {}, priority {}, deadline {})",
+                                        write.hdr.id, write.offset, write.hdr.block_size, write.hdr.priority, write.hdr.deadline));
                                 }
-                            } else {
-                                panic!("Try to re-establishing client connection in TCP !");
+                                if write.ends {
+                                    comments.push(format!("block {} ends at {}", write.hdr.id, end));
+                                }
+                                pcap.write_packet(get_current_usec(), Direction::Outbound, write.data, &comments)?;
                             }
-                            
+                            stream_offset.set(write.offset + write.data.len() as u64);
+                            // keeps the bytes written and in flight fresh while a large block is sent
+                            publish();
+                        },
+                        QueueEvent::Sent(sent) => {
+                            total_bytes.set(total_bytes.get() + sent.hdr.block_size);
+                            let sent = SentBlock {
+                                id: sent.hdr.id,
+                                iteration: schedule.iteration(block_index(sent.hdr.id)),
+                                block_size: sent.hdr.block_size,
+                                priority: sent.hdr.priority,
+                                deadline: sent.hdr.deadline,
+                                scheduled: sent.hdr.start_timestamp,
+                                first_write: sent.first_write,
+                                last_write: sent.last_write,
+                                would_block: sent.would_block,
+                            };
+                            if let Err(why) = send_log.write_all(sent.csv().as_bytes()) {
+                                panic!("couldn't write to {}: {}", send_log_path.display(), why);
+                            }
+                            sent_blocks.borrow_mut().push(sent);
+                            publish();
+                        },
+                        QueueEvent::Expired(_) => {
+                            expired_blocks.set(expired_blocks.get() + 1);
+                            publish();
                         },
-                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
-                        Err(err) => return Err(Box::new(err))
-                    }
-                },
-                // Write to the client
-                CLIENT => {
-                    if event.is_writable() {
-                        match client_stream {
-                            Some(ref mut stream) => { 
-                                debug!("writable!");
-                                'writable: loop {
-                                    // find more blocks to send
-                                    let c_time = get_current_usec();
-                                    if amount < cfgs.len() {
-                                        // check outdated blocks
-                                        while gap_sum[amount] + start_timestamp.clone().unwrap() > c_time {
-                                            amount += 1;
-                                            if amount >= cfgs.len() {
-                                                break;
-                                            }
-                                        }
-                                    }
-                                    if send_amount >= amount {
-                                        // wait until send the next block
-                                        if amount < cfgs.len() {
-                                            while get_current_usec() < start_timestamp.clone().unwrap() + gap_sum[amount] {
-                                                // wait
-                                            }
-                                            amount += 1;
-                                        } else {
-                                            break;
-                                        }
-                                    }
-
-                                    // send block
-                                    while send_amount < amount {
-                                        // prepare data
-                                        unsafe {
-                                            while(gap_sum[send_amount] + start_timestamp.clone().unwrap() > get_current_usec()){}
-                                            // create fake dtp header
-                                            let mut hdr: [u8; 40] = [0; 40];
-                                            let amount_bytes = ((send_amount * 4 + 5) as u64).to_be_bytes();
-                                            hdr[0..8].clone_from_slice(&amount_bytes);
-                                            let timestamp = start_timestamp.clone().unwrap() + gap_sum[send_amount];
-                                            let timestamp_bytes = timestamp.to_be_bytes();
-                                            hdr[8..16].clone_from_slice(&timestamp_bytes);
-                                            let block_size = 
-                                            if (cfgs[send_amount].block_size as usize) < MAX_BLOCK_SIZE {
-                                                cfgs[send_amount].block_size as usize
-                                            } else {
-                                                MAX_BLOCK_SIZE
-                                            } as u64; 
-                                            let block_size_bytes = block_size.to_be_bytes();
-                                            hdr[16..24].clone_from_slice(&block_size_bytes);
-                                            hdr[24..32].clone_from_slice(&(cfgs[send_amount].priority as u64).to_be_bytes());
-                                            hdr[32..40].clone_from_slice(&(cfgs[send_amount].deadline as u64).to_be_bytes());
-                                            let hdr_bytes = hdr;
-                                            for i in 0..hdr_bytes.len() {
-                                                DATA_BUF[i] = hdr_bytes[i];
-                                            }
-                                            // start writing
-                                            let send_len: usize = hdr_bytes.len() + block_size as usize;
-                                            // write block
-                                            'write: loop {
-                                                match stream.write(&DATA_BUF[total_size..send_len]) {
-                                                    Ok(size) => {
-                                                        if size == 0 {
-                                                            // connection closed
-                                                            break 'outer;
-                                                        }
-                                                        total_size += size;
-                                                        if total_size == send_len {
-                                                            total_size = 0;
-                                                            total_bytes += cfgs[send_amount].block_size as u64;
-                                                            debug!("{}: Write {} bytes!", send_amount, send_len);
-                                                            send_amount += 1; 
-                                                            break 'write;
-                                                        }
-                                                    },
-                                                    Err(err) => {
-                                                        if err.kind() == io::ErrorKind::WouldBlock {
-                                                            debug!("{}: Would Block, sent {}, remain {}", send_amount, total_size, send_len - total_size);
-                                                            break 'writable;
-                                                        } else {
-                                                            return Err(Box::new(err));
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            },
-                            None => panic!("Event is writable, but there is no client stream!")
-                        }
                     }
-                    if event.is_readable() {
-                        panic!("Something is readable in server, but it is abnormal...");
+                    Ok(())
+                })) };
+                tokio::pin!(sending);
+                loop {
+                    tokio::select! {
+                        res = &mut sending => break Some(res),
+                        signal = signals.recv() => {
+                            if run.interrupted.is_some() {
+                                eprintln!("{} again, quiting now", signal);
+                                break None;
+                            }
+                            eprintln!("{}, quiting after the block being sent", signal);
+                            run.interrupted = Some(signal.to_string());
+                            abort.abort();
+                        },
+                        _ = tick.tick(), if metrics.borrow().is_some() => publish(),
+                        _ = peer_abort(&mut receiver), if run.interrupted.is_none() => {
+                            eprintln!("the client aborted the run");
+                            run.interrupted = Some("client abort".to_string());
+                            abort.abort();
+                        },
                     }
-                },
-                _ => unreachable!()
+                }
+            };
+            if let Some((replayed, res)) = finished {
+                replayed?;
+                match res {
+                    Ok(_) if run.interrupted.is_none() => println!("Blocks send complete!"),
+                    Ok(_) => {},
+                    // connection closed
+                    Err(e) if e.kind() == io::ErrorKind::WriteZero => {},
+                    // the client may be gone already
+                    Err(e) if run.interrupted.is_some() => eprintln!("send stopped: {}", e),
+                    Err(e) => return Err(e.into()),
+                }
             }
-        }
-            
-        // check if configs are fully sent to the peer
-        if amount >= cfgs.len() && send_amount >= amount {
-            println!("Blocks send complete!");
-            break 'outer;
-        }
+            // the driver stops between blocks, not after a second signal
+            if driver.aborted() && receiver.aborted().is_none() {
+                if let Err(e) = driver.sender_mut().abort().await {
+                    eprintln!("couldn't send the abort message: {}", e);
+                }
+            }
+            client_stream = Some(driver.into_sender().into_inner());
+        },
+    }
+    let total_bytes = total_bytes.get();
+    let stream_offset = stream_offset.get();
+    let sent_blocks = sent_blocks.into_inner();
+    let expired_blocks = expired_blocks.get();
+    if let Some(ref mut pcap) = pcap {
+        pcap.flush()?;
+    }
+    send_log.flush()?;
+    if let Some(ref mut metrics) = *metrics.borrow_mut() {
+        let queue = client_stream.as_ref().and_then(|stream| send_queue_bytes(stream.as_ref().as_raw_fd()));
+        metrics.publish(server_metrics(&schedule, start_timestamp, sent_blocks.len(), expired_blocks, total_bytes, stream_offset, queue));
     }
-    let end_timestamp = Some(get_current_usec());
+    let end_timestamp = get_current_usec();
+    run.end_time = end_timestamp;
     eprintln!("connection closed, you can see result in client.log");
     
-    let mut total_time = 0;
-    if start_timestamp.is_none() {
-        eprintln!("no start time!");
+    let total_time = match start_timestamp {
+        Some(start) => end_timestamp - start,
+        None => {
+            eprintln!("no start time!");
+            0
+        },
+    };
+    let throughput = if (total_time as f64 / 1000.0 / 1000.0 ) == 0.0 {
+        99999999999999999.0
     } else {
-        total_time = end_timestamp.unwrap() - start_timestamp.unwrap(); 
+        total_bytes as f64 / (total_time as f64 / 1000.0 / 1000.0)
+    };
+    match run.interrupted {
+        None => eprintln!("total_bytes={}, total_time(us)={}, throughput(B/s)={}", total_bytes, total_time, throughput),
+        Some(ref why) => eprintln!("total_bytes={}, total_time(us)={}, throughput(B/s)={}, interrupted={}", total_bytes, total_time, throughput, why),
     }
-    let throughput: f64;
-    if (total_time as f64 / 1000.0 / 1000.0 ) == 0.0 {
-        throughput = 99999999999999999.0;
-    } else {
-        throughput = total_bytes as f64 / (total_time as f64 / 1000.0 / 1000.0);
+    if expired_blocks > 0 {
+        eprintln!("expired_blocks={}", expired_blocks);
+    }
+    let iterations = iteration_stats(&sent_blocks);
+    if iterations.len() > 1 {
+        for it in &iterations {
+            eprintln!("iteration={}, sent_blocks={}, total_bytes={}, total_time(us)={}, throughput(B/s)={}",
+                it.iteration, it.sent_blocks, it.total_bytes, it.total_time, it.throughput);
+        }
+    }
+    if !args.get_str("--results-json").is_empty() {
+        let results = serde_json::json!({
+            "run": run,
+            "blocks": sent_blocks,
+            "stats": {
+                "blocks": cfgs.len(),
+                "sent_blocks": sent_blocks.len(),
+                "expired_blocks": expired_blocks,
+                "total_bytes": total_bytes,
+                "total_time": total_time,
+                "throughput": throughput,
+                "iterations": iterations,
+            },
+        });
+        write_json(&output_path(args.get_str("--output-dir"), args.get_str("--results-json")), &results)?;
+    }
+    Ok(())
+}
+
+/// The time the client aborted the run at, never if it does not
+async fn peer_abort(receiver: &mut BlockReceiver<OwnedReadHalf>) -> u64 {
+    while let Ok(Some(_)) = receiver.next_block().await {}
+    match receiver.aborted() {
+        Some(at) => at,
+        None => std::future::pending().await,
+    }
+}
+
+// the page served on --metrics-addr, `send_amount` blocks are written and
+// `expired` dropped
+fn server_metrics(schedule: &Schedule, start_timestamp: Option<u64>, send_amount: usize, expired: usize, total_bytes: u64, stream_offset: u64, send_queue: Option<usize>) -> String {
+    // blocks whose send time has come
+    let due = match start_timestamp {
+        Some(start) => schedule.due(get_current_usec().saturating_sub(start)),
+        None => 0,
+    };
+    let mut page = MetricsPage::new();
+    page.single("dtp_server_blocks", "gauge", "Blocks in the trace.", schedule.trace_len() as f64)
+        .single("dtp_server_iteration", "gauge", "Iteration of the trace being sent, from 0.", schedule.iteration(send_amount) as f64)
+        .single("dtp_server_blocks_sent_total", "counter", "Blocks written to the socket completely.", send_amount as f64)
+        .single("dtp_server_blocks_expired_total", "counter", "Blocks dropped by --drop-expired before they were written.", expired as f64)
+        .single("dtp_server_blocks_queued", "gauge", "Blocks due to be sent that are not written completely.", due.saturating_sub(send_amount + expired) as f64)
+        .single("dtp_server_payload_bytes_sent_total", "counter", "Payload bytes of the blocks written completely.", total_bytes as f64)
+        .single("dtp_server_bytes_written_total", "counter", "Bytes written to the socket, headers included.", stream_offset as f64);
+    if let Some(send_queue) = send_queue {
+        page.single("dtp_server_bytes_in_flight", "gauge", "Bytes written to the socket and not acknowledged by the client yet.", send_queue as f64);
     }
-    eprintln!("total_bytes={}, total_time(us)={}, throughput(B/s)={}", total_bytes, total_time, throughput);
-    return Ok(());
+    page.render()
 }
-    
\ No newline at end of file
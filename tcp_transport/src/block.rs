@@ -0,0 +1,89 @@
+use dtp_utils::BlockHeader;
+
+use crate::payload::{BlockPayload, CHUNK_SIZE};
+
+/// The block being written: its header, then its payload a chunk at a time
+pub struct BlockBuf {
+    pub hdr: BlockHeader,
+    payload: BlockPayload,
+    // the header or the current chunk of payload
+    buf: Vec<u8>,
+    // bytes of `buf` written
+    pos: usize,
+    // payload bytes not generated yet
+    left: u64,
+    // header and payload bytes not written yet
+    remaining: u64,
+}
+
+impl BlockBuf {
+    pub fn new(hdr: BlockHeader, payload: BlockPayload) -> Self {
+        let buf = hdr.to_bytes();
+        BlockBuf {
+            remaining: buf.len() as u64 + hdr.block_size,
+            left: hdr.block_size,
+            hdr,
+            payload,
+            buf,
+            pos: 0,
+        }
+    }
+
+    /// Bytes to write next, empty once the block is written
+    pub fn pending(&mut self) -> &[u8] {
+        if self.pos == self.buf.len() && self.left > 0 {
+            let n = self.left.min(CHUNK_SIZE as u64) as usize;
+            self.buf.resize(n, 0);
+            self.payload.fill(&mut self.buf);
+            self.pos = 0;
+            self.left -= n as u64;
+        }
+        &self.buf[self.pos..]
+    }
+
+    /// `n` bytes of `pending` are written
+    pub fn advance(&mut self, n: usize) {
+        self.pos += n;
+        self.remaining -= n as u64;
+    }
+
+    /// Whether nothing of the block is written yet
+    pub fn is_new(&self) -> bool {
+        self.remaining == self.hdr.wire_len() as u64 + self.hdr.block_size
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunked() {
+        let size = 2 * CHUNK_SIZE as u64 + 10;
+        let hdr = BlockHeader { id: 5, block_size: size, checksum: Some(0), ..BlockHeader::default() };
+        let data = BlockPayload::Bytes { data: b"abc".to_vec().into(), pos: 0 };
+        let mut expected = hdr.to_bytes();
+        expected.extend(b"abc".iter().cycle().take(size as usize));
+
+        let mut block = BlockBuf::new(hdr, data);
+        assert!(block.is_new());
+        let mut written = Vec::new();
+        loop {
+            // a partial write as the socket does
+            let pending = block.pending();
+            let n = pending.len().min(1000);
+            if n == 0 {
+                break;
+            }
+            written.extend_from_slice(&pending[..n]);
+            block.advance(n);
+        }
+        assert_eq!(0, block.remaining());
+        assert!(!block.is_new());
+        assert_eq!(expected, written);
+    }
+}
@@ -0,0 +1,42 @@
+//! Blocks over a TCP stream, the sender and receiver of the TCP baseline
+//!
+//! `BlockSender` writes blocks to any `AsyncWrite`, and `BlockReceiver`
+//! parses them from any `AsyncRead` and hands their payload to the
+//! application. Blocks of an application are submitted
+//! to a `BlockQueue` with `send_block`, whose handle tells when the block is
+//! sent or expired, `replay` submits the blocks of a trace. The `tcp_server`
+//! and `tcp_client` binaries are built on them.
+#[macro_use]
+extern crate log;
+
+pub mod block;
+pub mod loopbytes;
+pub mod payload;
+pub mod queue;
+pub mod receiver;
+pub mod replay;
+pub mod schedule;
+pub mod sender;
+pub mod signals;
+pub mod streamparser;
+
+pub use queue::{block_queue, AbortHandle, BlockHandle, BlockQueue, BlockStatus};
+pub use receiver::{Block, BlockReceiver};
+pub use sender::BlockSender;
+
+/// A block parsed from the stream, timestamps are in microseconds
+#[derive(Clone, Debug, Default, Copy, PartialEq, serde::Serialize)]
+#[repr(C)]
+pub struct BlockInfo {
+    pub start_timestamp: u64,
+    pub end_timestamp: u64,
+    pub bct: u64, // ms
+    pub deadline: i32,
+    pub priority: i32,
+    pub block_size: u64,
+    pub id: u64,
+    /// Position of the block header in the stream
+    pub offset: u64,
+    /// CRC-32C of the payload given in the header
+    pub checksum: Option<u32>,
+}
@@ -0,0 +1,553 @@
+use std::cmp::{max, min};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use bytes::Buf;
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// Returned when the bytes do not fit into the buffer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PushError {
+    /// The buffer is full and is not allowed to grow any more
+    Full,
+}
+
+impl fmt::Display for PushError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PushError::Full => write!(f, "loop bytes buffer is full"),
+        }
+    }
+}
+
+impl Error for PushError {}
+
+/// A ring buffer of bytes
+///
+/// The buffer holds at most `capacity - 1` bytes. A growable buffer doubles its
+/// capacity when a push does not fit, until `max_capacity` is reached.
+pub struct LoopBytes {
+    pub bytes: Vec<u8>,
+    pub head: usize,
+    pub tail: usize,
+    pub length: usize,
+    pub capacity: usize,
+    pub max_capacity: usize
+}
+
+impl Default for LoopBytes {
+    fn default() -> Self {
+        LoopBytes::new(65535)
+    }
+}
+
+impl LoopBytes {
+    pub fn new(capacity: usize) -> Self {
+        LoopBytes {
+            bytes: vec![0; capacity],
+            head: 0,
+            tail: 0,
+            length: 0,
+            capacity,
+            max_capacity: capacity
+        }
+    }
+
+    /// A buffer that starts at `capacity` and grows up to `max_capacity`
+    pub fn with_max_capacity(capacity: usize, max_capacity: usize) -> Self {
+        LoopBytes {
+            max_capacity: max(capacity, max_capacity),
+            ..LoopBytes::new(capacity)
+        }
+    }
+
+    /// A buffer that grows without limit
+    pub fn growable(capacity: usize) -> Self {
+        LoopBytes::with_max_capacity(capacity, usize::MAX)
+    }
+
+    pub fn size(&self) -> usize {
+        self.length
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.capacity - 1 - self.size()
+    }
+
+    /// Bytes that can still be pushed if the buffer grows as far as it may
+    pub fn max_remaining(&self) -> usize {
+        self.max_capacity - 1 - self.size()
+    }
+
+    /// Push all of `buf` or nothing at all, growing the buffer if needed
+    pub fn try_push(&mut self, buf: &[u8]) -> Result<(), PushError> {
+        if buf.len() > self.max_remaining() {
+            return Err(PushError::Full);
+        }
+        self.reserve(buf.len());
+        let pushed = self.push(buf, buf.len());
+        assert_eq!(pushed, buf.len());
+        Ok(())
+    }
+
+    // push the bytes from the buffer to the loop array, the buffer grows if it
+    // may, otherwise only the bytes that fit are pushed
+    #[must_use = "bytes that do not fit are not pushed"]
+    pub fn push(&mut self, buf: &[u8], size: usize) -> usize {
+        if size == 0 {
+            return 0;
+        }
+        self.reserve(size);
+        let remaining = self.remaining();
+        if remaining > 0 {
+            let push_size = min(size, remaining);
+            if self.tail + push_size < self.capacity {
+                self.bytes[self.tail..self.tail + push_size].clone_from_slice(&buf[..push_size]);
+                self.tail += push_size;
+            } else {
+                assert!(self.head <= self.tail);
+                let remain = push_size - (self.capacity - self.tail);
+                self.bytes[self.tail..self.capacity].clone_from_slice(&buf[..push_size - remain]);
+                self.bytes[..remain].clone_from_slice(&buf[push_size - remain..push_size]);
+                self.tail += push_size;
+                self.tail -= self.capacity;
+            }
+            self.length += push_size;
+            push_size
+        } else {
+            0
+        }
+    }
+
+    // pop the first s bytes of content in the buffer
+    pub fn pop(&mut self, buf: &mut[u8], s: usize) -> usize {
+        if s == 0 {
+            return 0;
+        }
+        let size = self.size();
+        if size > 0 {
+            let pop_size = min(s, size); 
+            if self.head + pop_size < self.capacity {
+                buf[..pop_size].clone_from_slice(&self.bytes[self.head..self.head + pop_size]);
+                self.head += pop_size;
+            } else {
+                assert!(self.head >= self.tail);
+                let remain = pop_size - (self.capacity - self.head);
+                buf[..pop_size - remain].clone_from_slice(&self.bytes[self.head..self.capacity]);
+                buf[pop_size - remain..pop_size].clone_from_slice(&self.bytes[..remain]);
+                self.head += pop_size;
+                self.head -= self.capacity;
+            }
+            self.length -= pop_size;
+            pop_size
+        } else {
+            0
+        }
+    }
+
+    // remove the first s bytes without returning the content
+    pub fn drop(&mut self, s: usize) -> usize {
+        if s == 0 {
+            return 0;
+        }
+        let size = self.size();
+        if size > 0 {
+            let pop_size = min(s, size); 
+            self.head += pop_size;
+            if self.head >= self.capacity {
+                assert!(self.head - pop_size >= self.tail);
+                self.head -= self.capacity;
+            }
+            self.length -= pop_size;
+            pop_size
+        } else {
+            0
+        }
+    }
+
+    // make room for `additional` more bytes, as far as max_capacity allows
+    fn reserve(&mut self, additional: usize) {
+        if additional <= self.remaining() || self.capacity == self.max_capacity {
+            return;
+        }
+        let wanted = self.size().saturating_add(additional).saturating_add(1);
+        let capacity = min(max(self.capacity.saturating_mul(2), wanted), self.max_capacity);
+        let mut bytes = vec![0; capacity];
+        let (first, second) = self.as_slices();
+        bytes[..first.len()].copy_from_slice(first);
+        bytes[first.len()..self.length].copy_from_slice(second);
+        debug!("loop bytes grow from {} to {}", self.capacity, capacity);
+        self.bytes = bytes;
+        self.head = 0;
+        self.tail = self.length;
+        self.capacity = capacity;
+    }
+
+    // copy the first s bytes of content in the buffer without removing them
+    pub fn peek(&self, buf: &mut [u8], s: usize) -> usize {
+        let (first, second) = self.as_slices();
+        let peek_size = min(s, self.size());
+        let n = min(peek_size, first.len());
+        buf[..n].copy_from_slice(&first[..n]);
+        buf[n..peek_size].copy_from_slice(&second[..peek_size - n]);
+        peek_size
+    }
+
+    /// The content of the buffer as two contiguous slices, in order
+    pub fn as_slices(&self) -> (&[u8], &[u8]) {
+        if self.head + self.length <= self.capacity {
+            (&self.bytes[self.head..self.head + self.length], &[])
+        } else {
+            (&self.bytes[self.head..], &self.bytes[..self.tail])
+        }
+    }
+
+    /// The last `n` bytes of content as two contiguous slices, in order
+    pub fn last_slices(&self, n: usize) -> (&[u8], &[u8]) {
+        let (first, second) = self.as_slices();
+        let n = min(n, self.size());
+        if n <= second.len() {
+            (&second[second.len() - n..], &[])
+        } else {
+            (&first[first.len() - (n - second.len())..], second)
+        }
+    }
+
+    /// Read from `reader` straight into the free space of the buffer
+    ///
+    /// Like `Read::read`, returns `Ok(0)` on EOF. If the buffer is full and
+    /// cannot grow it returns `Ok(0)` as well without touching the reader, so
+    /// check `max_remaining()` first.
+    pub fn read_from<R: Read>(&mut self, reader: &mut R) -> io::Result<usize> {
+        let mut total = 0;
+        while let Some(free) = self.free_space() {
+            let len = free.len();
+            let size = match reader.read(&mut self.bytes[free]) {
+                Ok(size) => size,
+                Err(e) if total > 0 && e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            };
+            self.filled(size);
+            total += size;
+            if size < len {
+                // EOF, or the reader has nothing more for now
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    /// Read once from an async `reader` into the free space of the buffer
+    ///
+    /// `Ok(0)` is EOF or a full buffer as with `read_from`.
+    pub fn poll_read_from<R: AsyncRead + Unpin>(&mut self, cx: &mut Context<'_>, reader: &mut R) -> Poll<io::Result<usize>> {
+        let free = match self.free_space() {
+            Some(free) => free,
+            None => return Poll::Ready(Ok(0)),
+        };
+        let mut buf = ReadBuf::new(&mut self.bytes[free]);
+        ready!(Pin::new(reader).poll_read(cx, &mut buf))?;
+        let size = buf.filled().len();
+        self.filled(size);
+        Poll::Ready(Ok(size))
+    }
+
+    // the first contiguous free range of `bytes`, growing the buffer if it is
+    // full, `None` if it cannot grow
+    fn free_space(&mut self) -> Option<Range<usize>> {
+        if self.remaining() == 0 {
+            self.reserve(1);
+            if self.remaining() == 0 {
+                return None;
+            }
+        }
+        // the free space is bytes[tail..] and bytes[..head - 1], one slot
+        // always stays empty to tell a full buffer from an empty one
+        let end = if self.tail >= self.head {
+            if self.head == 0 { self.capacity - 1 } else { self.capacity }
+        } else {
+            self.head - 1
+        };
+        Some(self.tail..end)
+    }
+
+    // `size` bytes were written at the start of `free_space()`
+    fn filled(&mut self, size: usize) {
+        self.tail += size;
+        if self.tail == self.capacity {
+            self.tail = 0;
+        }
+        self.length += size;
+    }
+
+    /// A `bytes::Buf` over the content, advancing it removes the bytes
+    pub fn buf(&mut self) -> LoopBuf<'_> {
+        LoopBuf { bytes: self }
+    }
+}
+
+/// `bytes::Buf` view of a `LoopBytes`
+pub struct LoopBuf<'a> {
+    bytes: &'a mut LoopBytes,
+}
+
+impl<'a> Buf for LoopBuf<'a> {
+    fn remaining(&self) -> usize {
+        self.bytes.size()
+    }
+
+    fn chunk(&self) -> &[u8] {
+        self.bytes.as_slices().0
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        assert!(cnt <= self.bytes.size(), "cannot advance past the end of LoopBytes");
+        self.bytes.drop(cnt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use proptest::prelude::*;
+
+    #[test]
+    fn push() {
+        let mut loopbytes = LoopBytes::new(128);
+        let mut buf: [u8; 128] = [0; 128];
+        assert_eq!(100, loopbytes.push(&buf, 100));
+        assert_eq!(100, loopbytes.tail);
+        assert_eq!(27, loopbytes.push(&buf, 100));
+        assert_eq!(127, loopbytes.tail);
+        loopbytes.pop(&mut buf, 100);
+        assert_eq!(100, loopbytes.head);
+        assert_eq!(100, loopbytes.remaining());
+        assert_eq!(127, loopbytes.tail);
+        assert_eq!(100, loopbytes.push(&buf, 100));
+        assert_eq!(99, loopbytes.tail);
+    }
+
+
+    #[test]
+    fn pop() {
+        let mut loopbytes = LoopBytes::new(128);
+        let mut buf: [u8; 128] = [0; 128];
+        assert_eq!(127, loopbytes.push(&buf, 127));
+        assert_eq!(127, loopbytes.pop(&mut buf, 127));
+        assert_eq!(127, loopbytes.head);
+        assert_eq!(127, loopbytes.remaining());
+        assert_eq!(100, loopbytes.push(&buf, 100));
+        assert_eq!(127, loopbytes.head);
+        assert_eq!(100, loopbytes.pop(&mut buf, 100));
+    }
+
+    #[test]
+    fn peek_and_slices() {
+        let mut loopbytes = LoopBytes::new(8);
+        let mut buf: [u8; 8] = [0; 8];
+        assert_eq!(5, loopbytes.push(&[1, 2, 3, 4, 5], 5));
+        assert_eq!(4, loopbytes.pop(&mut buf, 4));
+        assert_eq!(5, loopbytes.push(&[6, 7, 8, 9, 10], 5));
+        assert_eq!((&[5, 6, 7, 8][..], &[9, 10][..]), loopbytes.as_slices());
+        assert_eq!((&[10][..], &[][..]), loopbytes.last_slices(1));
+        assert_eq!((&[7, 8][..], &[9, 10][..]), loopbytes.last_slices(4));
+        assert_eq!((&[5, 6, 7, 8][..], &[9, 10][..]), loopbytes.last_slices(100));
+        assert_eq!(6, loopbytes.peek(&mut buf, 8));
+        assert_eq!([5, 6, 7, 8, 9, 10], buf[..6]);
+        assert_eq!(6, loopbytes.size());
+        assert_eq!(2, loopbytes.drop(2));
+        assert_eq!((&[7, 8][..], &[9, 10][..]), loopbytes.as_slices());
+        assert_eq!(4, loopbytes.drop(10));
+        assert_eq!((&[][..], &[][..]), loopbytes.as_slices());
+    }
+
+    #[test]
+    fn read_from() {
+        let mut loopbytes = LoopBytes::new(8);
+        let mut buf: [u8; 8] = [0; 8];
+        let data: Vec<u8> = (0..20).collect();
+        let mut reader = &data[..];
+        assert_eq!(7, loopbytes.read_from(&mut reader).unwrap());
+        assert_eq!(0, loopbytes.read_from(&mut reader).unwrap());
+        assert_eq!(5, loopbytes.pop(&mut buf, 5));
+        // wraps around the end of the array
+        assert_eq!(5, loopbytes.read_from(&mut reader).unwrap());
+        assert_eq!((&[5, 6, 7][..], &[8, 9, 10, 11][..]), loopbytes.as_slices());
+        assert_eq!(7, loopbytes.pop(&mut buf, 8));
+        assert_eq!(7, loopbytes.read_from(&mut reader).unwrap());
+        assert_eq!(7, loopbytes.pop(&mut buf, 8));
+        assert_eq!([12, 13, 14, 15, 16, 17, 18], buf[..7]);
+        assert_eq!(1, loopbytes.read_from(&mut reader).unwrap());
+        assert_eq!(0, loopbytes.read_from(&mut reader).unwrap());
+        assert_eq!(1, loopbytes.size());
+    }
+
+    #[tokio::test]
+    async fn poll_read_from() {
+        let mut loopbytes = LoopBytes::new(8);
+        let mut buf: [u8; 8] = [0; 8];
+        let data: Vec<u8> = (0..20).collect();
+        let mut reader = &data[..];
+        async fn read(loopbytes: &mut LoopBytes, reader: &mut &[u8]) -> io::Result<usize> {
+            std::future::poll_fn(|cx| loopbytes.poll_read_from(cx, reader)).await
+        }
+        assert_eq!(7, read(&mut loopbytes, &mut reader).await.unwrap());
+        assert_eq!(0, read(&mut loopbytes, &mut reader).await.unwrap());
+        assert_eq!(5, loopbytes.pop(&mut buf, 5));
+        // one read stops at the end of the array
+        assert_eq!(1, read(&mut loopbytes, &mut reader).await.unwrap());
+        assert_eq!(4, read(&mut loopbytes, &mut reader).await.unwrap());
+        assert_eq!((&[5, 6, 7][..], &[8, 9, 10, 11][..]), loopbytes.as_slices());
+    }
+
+    #[test]
+    fn buf() {
+        let mut loopbytes = LoopBytes::new(8);
+        let mut buf: [u8; 8] = [0; 8];
+        assert_eq!(6, loopbytes.push(&[1, 2, 3, 4, 5, 6], 6));
+        assert_eq!(4, loopbytes.pop(&mut buf, 4));
+        assert_eq!(4, loopbytes.push(&[7, 8, 9, 10], 4));
+        let mut view = loopbytes.buf();
+        assert_eq!(6, view.remaining());
+        assert_eq!(&[5, 6, 7, 8], view.chunk());
+        assert_eq!(0x0506_0708_090a, view.get_uint(6));
+        assert_eq!(0, loopbytes.size());
+    }
+
+    #[test]
+    fn try_push() {
+        let mut loopbytes = LoopBytes::new(8);
+        let mut buf: [u8; 8] = [0; 8];
+        assert_eq!(Ok(()), loopbytes.try_push(&[1, 2, 3, 4, 5]));
+        assert_eq!(Err(PushError::Full), loopbytes.try_push(&[6, 7, 8]));
+        assert_eq!(5, loopbytes.size());
+        assert_eq!(Ok(()), loopbytes.try_push(&[6, 7]));
+        assert_eq!(Err(PushError::Full), loopbytes.try_push(&[8]));
+        assert_eq!(7, loopbytes.pop(&mut buf, 8));
+        assert_eq!([1, 2, 3, 4, 5, 6, 7], buf[..7]);
+    }
+
+    #[test]
+    fn grow() {
+        let mut loopbytes = LoopBytes::with_max_capacity(8, 20);
+        let mut buf: [u8; 20] = [0; 20];
+        assert_eq!(6, loopbytes.push(&[1, 2, 3, 4, 5, 6], 6));
+        assert_eq!(4, loopbytes.pop(&mut buf, 4));
+        // wrapped content is kept in order when the buffer grows
+        assert_eq!(Ok(()), loopbytes.try_push(&[7, 8, 9, 10, 11, 12, 13, 14]));
+        assert_eq!(16, loopbytes.capacity);
+        assert_eq!(Ok(()), loopbytes.try_push(&[15, 16, 17]));
+        assert_eq!(16, loopbytes.capacity);
+        // never beyond max_capacity
+        assert_eq!(Err(PushError::Full), loopbytes.try_push(&[18, 19, 20, 21, 22, 23, 24]));
+        assert_eq!(6, loopbytes.push(&[18, 19, 20, 21, 22, 23, 24], 7));
+        assert_eq!(20, loopbytes.capacity);
+        assert_eq!(0, loopbytes.max_remaining());
+        assert_eq!(19, loopbytes.pop(&mut buf, 20));
+        assert_eq!((5..=23).collect::<Vec<u8>>(), buf[..19].to_vec());
+
+        let mut loopbytes = LoopBytes::growable(4);
+        let data: Vec<u8> = (0..100).collect();
+        let mut reader = &data[..];
+        let mut total = 0;
+        while total < data.len() {
+            total += loopbytes.read_from(&mut reader).unwrap();
+        }
+        assert_eq!(100, loopbytes.size());
+        let (first, second) = loopbytes.as_slices();
+        assert_eq!(data, [first, second].concat());
+    }
+
+    #[derive(Clone, Debug)]
+    enum Op {
+        Push(Vec<u8>),
+        TryPush(Vec<u8>),
+        ReadFrom(Vec<u8>),
+        Pop(usize),
+        Peek(usize),
+        Drop(usize),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        let bytes = prop::collection::vec(any::<u8>(), 0..40);
+        prop_oneof![
+            bytes.clone().prop_map(Op::Push),
+            bytes.clone().prop_map(Op::TryPush),
+            bytes.prop_map(Op::ReadFrom),
+            (0..40usize).prop_map(Op::Pop),
+            (0..40usize).prop_map(Op::Peek),
+            (0..40usize).prop_map(Op::Drop),
+        ]
+    }
+
+    // run `ops` on both a LoopBytes and a VecDeque holding at most `max_capacity - 1` bytes
+    fn check_model(mut loopbytes: LoopBytes, max_capacity: usize, ops: Vec<Op>) -> Result<(), TestCaseError> {
+        let mut model: VecDeque<u8> = VecDeque::new();
+        let mut buf = [0; 40];
+        for op in ops {
+            let room = max_capacity - 1 - model.len();
+            match op {
+                Op::Push(bytes) => {
+                    let pushed = loopbytes.push(&bytes, bytes.len());
+                    prop_assert_eq!(pushed, min(room, bytes.len()));
+                    model.extend(&bytes[..pushed]);
+                }
+                Op::TryPush(bytes) => {
+                    let res = loopbytes.try_push(&bytes);
+                    if bytes.len() <= room {
+                        prop_assert_eq!(res, Ok(()));
+                        model.extend(&bytes);
+                    } else {
+                        prop_assert_eq!(res, Err(PushError::Full));
+                    }
+                }
+                Op::ReadFrom(bytes) => {
+                    let mut reader = &bytes[..];
+                    let read = loopbytes.read_from(&mut reader).unwrap();
+                    prop_assert_eq!(read, min(room, bytes.len()));
+                    model.extend(&bytes[..read]);
+                }
+                Op::Pop(n) => {
+                    let popped = loopbytes.pop(&mut buf, n);
+                    let expected: Vec<u8> = model.drain(..min(n, model.len())).collect();
+                    prop_assert_eq!(&buf[..popped], &expected[..]);
+                }
+                Op::Peek(n) => {
+                    let peeked = loopbytes.peek(&mut buf, n);
+                    let expected: Vec<u8> = model.iter().take(n).cloned().collect();
+                    prop_assert_eq!(&buf[..peeked], &expected[..]);
+                }
+                Op::Drop(n) => {
+                    let dropped = loopbytes.drop(n);
+                    prop_assert_eq!(dropped, min(n, model.len()));
+                    model.drain(..dropped);
+                }
+            }
+            prop_assert_eq!(loopbytes.size(), model.len());
+            prop_assert_eq!(loopbytes.max_remaining(), max_capacity - 1 - model.len());
+            prop_assert!(loopbytes.head < loopbytes.capacity);
+            prop_assert!(loopbytes.tail < loopbytes.capacity);
+            let (first, second) = loopbytes.as_slices();
+            prop_assert_eq!([first, second].concat(), model.iter().cloned().collect::<Vec<u8>>());
+        }
+        Ok(())
+    }
+
+    proptest! {
+        // small capacities hit the wraparound around `capacity - 1` all the time
+        #[test]
+        fn model_fixed(capacity in 2..20usize, ops in prop::collection::vec(op(), 0..100)) {
+            check_model(LoopBytes::new(capacity), capacity, ops)?;
+        }
+
+        #[test]
+        fn model_growable(capacity in 2..20usize, extra in 0..60usize, ops in prop::collection::vec(op(), 0..100)) {
+            check_model(LoopBytes::with_max_capacity(capacity, capacity + extra), capacity + extra, ops)?;
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use bytes::Bytes;
+use dtp_utils::crc32c::Crc32c;
+use dtp_utils::tracegen::Rng;
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Bytes of payload generated at a time, a block is never in memory as a whole
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Where the bytes of a block after its header come from
+pub enum Payload {
+    Zeros,
+    /// every block from a fresh random seed, nothing on the path can
+    /// compress them
+    Random(SystemRandom),
+    /// the file repeated, every block continues where the previous one stopped
+    File { data: Bytes, pos: usize },
+    /// block `i` of the trace is the file `i % files.len()` of a directory in
+    /// name order, repeated or cut to the block size
+    Dir(Vec<Bytes>),
+}
+
+impl Payload {
+    /// `zeros`, `random`, `file:PATH` or `dir:PATH`
+    pub fn parse(spec: &str) -> Result<Self, Box<dyn Error>> {
+        match spec.split_once(':') {
+            None if spec == "zeros" => Ok(Payload::Zeros),
+            None if spec == "random" => Ok(Payload::Random(SystemRandom::new())),
+            Some(("file", path)) => {
+                let data = fs::read(path).map_err(|e| format!("couldn't read {}: {}", path, e))?;
+                if data.is_empty() {
+                    return Err(format!("{} is empty", path).into());
+                }
+                Ok(Payload::File { data: data.into(), pos: 0 })
+            },
+            Some(("dir", path)) => {
+                let mut paths = Vec::new();
+                for entry in fs::read_dir(path).map_err(|e| format!("couldn't read {}: {}", path, e))? {
+                    let entry = entry?;
+                    if entry.file_type()?.is_file() {
+                        paths.push(entry.path());
+                    }
+                }
+                paths.sort();
+                let mut files = Vec::new();
+                for path in paths {
+                    let data = fs::read(&path)?;
+                    if !data.is_empty() {
+                        files.push(data.into());
+                    }
+                }
+                if files.is_empty() {
+                    return Err(format!("no file in {}", Path::new(path).display()).into());
+                }
+                Ok(Payload::Dir(files))
+            },
+            _ => Err(format!("invalid payload {}, expected zeros, random, file:PATH or dir:PATH", spec).into()),
+        }
+    }
+
+    /// Blocks of `block_sizes` whose size differs from their file, only
+    /// a directory can mismatch
+    pub fn mismatches(&self, block_sizes: &[u64]) -> usize {
+        match self {
+            Payload::Dir(files) if !files.is_empty() => block_sizes
+                .iter()
+                .enumerate()
+                .filter(|(i, size)| files[i % files.len()].len() as u64 != **size)
+                .count(),
+            _ => 0,
+        }
+    }
+
+    /// The payload of block `index` of the trace, `size` bytes long
+    pub fn block(&mut self, index: usize, size: u64) -> Result<BlockPayload, Box<dyn Error>> {
+        let empty = match self {
+            Payload::File { data, .. } => data.is_empty(),
+            Payload::Dir(files) => files.iter().all(|data| data.is_empty()),
+            _ => false,
+        };
+        if empty {
+            return Err("the payload has no bytes to repeat".into());
+        }
+        Ok(match self {
+            Payload::Zeros => BlockPayload::Zeros,
+            Payload::Random(random) => {
+                let mut seed = [0; 8];
+                random.fill(&mut seed).map_err(|_| "couldn't generate random bytes")?;
+                BlockPayload::Random { rng: Rng::new(u64::from_le_bytes(seed)), word: [0; 8], used: 8 }
+            },
+            Payload::File { data, pos } => {
+                let start = *pos;
+                *pos = ((*pos as u64 + size) % data.len() as u64) as usize;
+                BlockPayload::Bytes { data: data.clone(), pos: start }
+            },
+            Payload::Dir(files) => BlockPayload::Bytes { data: files[index % files.len()].clone(), pos: 0 },
+        })
+    }
+}
+
+/// The payload of one block, generated a chunk at a time
+///
+/// A clone generates the same bytes, so the checksum of a block is known
+/// before it is sent.
+#[derive(Clone)]
+pub enum BlockPayload {
+    Zeros,
+    /// the bytes of a seeded generator, `used` bytes of `word` are taken
+    Random { rng: Rng, word: [u8; 8], used: usize },
+    /// `data` repeated from `pos` on, zeros if it is empty
+    Bytes { data: Bytes, pos: usize },
+}
+
+impl BlockPayload {
+    /// Fill `buf` with the next bytes of the payload
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        match self {
+            BlockPayload::Zeros => buf.fill(0),
+            BlockPayload::Random { rng, word, used } => {
+                for b in buf.iter_mut() {
+                    if *used == word.len() {
+                        *word = rng.next_u64().to_le_bytes();
+                        *used = 0;
+                    }
+                    *b = word[*used];
+                    *used += 1;
+                }
+            },
+            // nothing to repeat
+            BlockPayload::Bytes { data, .. } if data.is_empty() => buf.fill(0),
+            BlockPayload::Bytes { data, pos } => {
+                *pos %= data.len();
+                let mut filled = 0;
+                while filled < buf.len() {
+                    let n = (buf.len() - filled).min(data.len() - *pos);
+                    buf[filled..filled + n].copy_from_slice(&data[*pos..*pos + n]);
+                    filled += n;
+                    *pos = (*pos + n) % data.len();
+                }
+            },
+        }
+    }
+
+    /// CRC-32C of the next `size` bytes, the payload itself does not advance
+    pub fn checksum(&self, size: u64) -> u32 {
+        let mut payload = self.clone();
+        let mut chunk = vec![0; (size.min(CHUNK_SIZE as u64)) as usize];
+        let mut crc = Crc32c::new();
+        let mut left = size;
+        while left > 0 {
+            let n = left.min(chunk.len() as u64) as usize;
+            payload.fill(&mut chunk[..n]);
+            crc.update(&chunk[..n]);
+            left -= n as u64;
+        }
+        crc.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dtp_utils::crc32c::crc32c;
+
+    fn bytes(payload: &mut Payload, index: usize, size: usize) -> Vec<u8> {
+        let mut buf = vec![1; size];
+        payload.block(index, size as u64).unwrap().fill(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn sources() {
+        assert_eq!(vec![0; 5], bytes(&mut Payload::parse("zeros").unwrap(), 0, 5));
+
+        let mut random = Payload::parse("random").unwrap();
+        assert_ne!(bytes(&mut random, 0, 64), bytes(&mut random, 1, 64));
+
+        let dir = std::env::temp_dir().join(format!("payload_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a"), b"abc").unwrap();
+        fs::write(dir.join("b"), b"xy").unwrap();
+
+        let mut file = Payload::parse(&format!("file:{}", dir.join("a").display())).unwrap();
+        assert_eq!(b"abca", bytes(&mut file, 0, 4).as_slice());
+        assert_eq!(b"bcab", bytes(&mut file, 1, 4).as_slice());
+
+        let mut files = Payload::parse(&format!("dir:{}", dir.display())).unwrap();
+        assert_eq!(b"xyx", bytes(&mut files, 1, 3).as_slice());
+        assert_eq!(b"abc", bytes(&mut files, 2, 3).as_slice());
+        assert_eq!(1, files.mismatches(&[3, 3, 3]));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(Payload::parse("file:/nonexistent").is_err());
+        assert!(Payload::parse("ones").is_err());
+    }
+
+    #[test]
+    fn chunks() {
+        // the same bytes however the payload is cut into chunks
+        let mut random = Payload::parse("random").unwrap();
+        let block = random.block(0, 1000).unwrap();
+        let mut whole = vec![0; 1000];
+        block.clone().fill(&mut whole);
+        let mut parts = block.clone();
+        let mut chunked = vec![0; 1000];
+        for chunk in chunked.chunks_mut(13) {
+            parts.fill(chunk);
+        }
+        assert_eq!(whole, chunked);
+        assert_eq!(crc32c(&whole), block.checksum(1000));
+
+        let mut bytes = BlockPayload::Bytes { data: b"abcde".to_vec().into(), pos: 3 };
+        let mut buf = [0; 7];
+        bytes.fill(&mut buf[..4]);
+        bytes.fill(&mut buf[4..]);
+        assert_eq!(b"deabcde", &buf);
+        let mut empty = BlockPayload::Bytes { data: Bytes::new(), pos: 0 };
+        empty.fill(&mut buf);
+        assert_eq!([0; 7], buf);
+        assert!(Payload::File { data: Bytes::new(), pos: 0 }.block(0, 10).is_err());
+        assert!(Payload::Dir(vec![Bytes::new()]).block(0, 10).is_err());
+        assert_eq!(0, Payload::Dir(vec![]).mismatches(&[3]));
+        assert_eq!(crc32c(&[0; 200_000]), BlockPayload::Zeros.checksum(200_000));
+    }
+}
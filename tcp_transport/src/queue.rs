@@ -0,0 +1,438 @@
+use std::cmp::Reverse;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use dtp_utils::{get_current_usec, BlockHeader};
+use tokio::io::AsyncWrite;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::payload::BlockPayload;
+use crate::replay::block_id;
+use crate::sender::{BlockSender, Sent, Write};
+
+/// What became of a block given to a `BlockQueue`
+#[derive(Clone, Copy, Debug)]
+pub enum BlockStatus {
+    /// written completely to the stream
+    Sent(Sent),
+    /// its deadline passed before it was picked, nothing of it is sent,
+    /// `at` is the unix time in us it was dropped
+    Expired { at: u64 },
+    /// the queue stopped before the block was written, by an error of the
+    /// stream or as the `QueueDriver` was dropped, or the block is empty
+    Aborted,
+}
+
+/// A block in a `BlockQueue`, tells when it is sent or expired
+pub struct BlockHandle {
+    id: u64,
+    status: oneshot::Receiver<BlockStatus>,
+}
+
+impl BlockHandle {
+    /// Id of the block in its header
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The status of the block, `None` while it is queued or being written
+    pub fn try_status(&mut self) -> Option<BlockStatus> {
+        match self.status.try_recv() {
+            Ok(status) => Some(status),
+            Err(oneshot::error::TryRecvError::Empty) => None,
+            Err(oneshot::error::TryRecvError::Closed) => Some(BlockStatus::Aborted),
+        }
+    }
+
+    /// Wait until the block is sent, expired or aborted
+    pub async fn status(self) -> BlockStatus {
+        self.status.await.unwrap_or(BlockStatus::Aborted)
+    }
+}
+
+/// What the `QueueDriver` does with the blocks
+#[derive(Clone, Copy, Debug)]
+pub enum QueueEvent<'a> {
+    Write(Write<'a>),
+    /// a block is written completely
+    Sent(&'a Sent),
+    /// a block is dropped as its deadline passed
+    Expired(&'a BlockHeader),
+}
+
+struct Queued {
+    hdr: BlockHeader,
+    payload: BlockPayload,
+    status: oneshot::Sender<BlockStatus>,
+}
+
+enum Msg {
+    Block(Queued),
+    Abort,
+}
+
+/// Where blocks are submitted, clones submit to the same queue
+///
+/// The blocks are written by the `QueueDriver` made along with it, which
+/// stops once every `BlockQueue` is dropped and the queue is empty.
+#[derive(Clone)]
+pub struct BlockQueue {
+    tx: mpsc::UnboundedSender<Msg>,
+    // blocks submitted, each gets the next id
+    blocks: Arc<AtomicUsize>,
+}
+
+/// A queue of blocks written to `sender`
+pub fn block_queue<W>(sender: BlockSender<W>) -> (BlockQueue, QueueDriver<W>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let queue = BlockQueue { tx, blocks: Arc::new(AtomicUsize::new(0)) };
+    let driver = QueueDriver { sender, rx, pending: Vec::new(), aborted: false, by_priority: false, drop_expired: false, checksum: false };
+    (queue, driver)
+}
+
+impl BlockQueue {
+    /// Queue `data` as a block, it is due `deadline` ms from now
+    pub fn send_block(&self, data: Bytes, deadline: u64, priority: u64) -> BlockHandle {
+        let hdr = BlockHeader {
+            id: 0,
+            start_timestamp: get_current_usec(),
+            block_size: data.len() as u64,
+            priority,
+            deadline,
+            checksum: None,
+        };
+        self.submit(hdr, BlockPayload::Bytes { data, pos: 0 })
+    }
+
+    /// Queue a block of `hdr.block_size` bytes of `payload`, its header is
+    /// sent as is but for the id
+    ///
+    /// Every block of the queue, whoever submits it, gets the next id of
+    /// `replay::block_id`, so they never repeat. The deadline is counted
+    /// from `hdr.start_timestamp`. A block submitted after the driver
+    /// stopped is aborted, and so is an empty one at once, with the id 0,
+    /// as the receiver would reject its header.
+    pub fn submit(&self, mut hdr: BlockHeader, payload: BlockPayload) -> BlockHandle {
+        let (tx, rx) = oneshot::channel();
+        if hdr.block_size == 0 {
+            let _ = tx.send(BlockStatus::Aborted);
+            return BlockHandle { id: 0, status: rx };
+        }
+        hdr.id = block_id(self.blocks.fetch_add(1, Ordering::Relaxed));
+        // the block is dropped along with `tx` if the driver stopped
+        let _ = self.tx.send(Msg::Block(Queued { hdr, payload, status: tx }));
+        BlockHandle { id: hdr.id, status: rx }
+    }
+
+    /// Whether the driver stopped, nothing submitted is sent anymore
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    /// Wait until the driver stopped
+    pub async fn closed(&self) {
+        self.tx.closed().await
+    }
+
+    /// A handle that stops the driver, it does not keep the queue open
+    pub fn abort_handle(&self) -> AbortHandle {
+        AbortHandle { tx: self.tx.downgrade() }
+    }
+}
+
+/// Stops a `QueueDriver`, made by `BlockQueue::abort_handle`
+#[derive(Clone)]
+pub struct AbortHandle {
+    tx: mpsc::WeakUnboundedSender<Msg>,
+}
+
+impl AbortHandle {
+    /// Stop the driver once the block being written is finished, the blocks
+    /// still queued are aborted
+    ///
+    /// Nothing happens if the driver stopped already.
+    pub fn abort(&self) {
+        if let Some(tx) = self.tx.upgrade() {
+            let _ = tx.send(Msg::Abort);
+        }
+    }
+}
+
+/// Writes the blocks of a `BlockQueue` one after another
+///
+/// By default the blocks are written in the order they are submitted and
+/// none is dropped, as the TCP baseline does.
+pub struct QueueDriver<W> {
+    sender: BlockSender<W>,
+    rx: mpsc::UnboundedReceiver<Msg>,
+    // submitted, not picked yet, in the order of submission
+    pending: Vec<Queued>,
+    // by an `AbortHandle`
+    aborted: bool,
+    by_priority: bool,
+    drop_expired: bool,
+    checksum: bool,
+}
+
+impl<W: AsyncWrite + Unpin> QueueDriver<W> {
+    /// Pick the queued block of the highest priority value first, the
+    /// earliest submitted among equals
+    pub fn by_priority(mut self, on: bool) -> Self {
+        self.by_priority = on;
+        self
+    }
+
+    /// Drop the blocks whose deadline passed while they were queued
+    ///
+    /// The queue is checked whenever a block is picked, a block being
+    /// written is always finished.
+    pub fn drop_expired(mut self, on: bool) -> Self {
+        self.drop_expired = on;
+        self
+    }
+
+    /// Put the CRC-32C of the payload in the headers without one, a pass
+    /// over the payload before it is sent
+    pub fn checksum(mut self, on: bool) -> Self {
+        self.checksum = on;
+        self
+    }
+
+    pub fn sender(&self) -> &BlockSender<W> {
+        &self.sender
+    }
+
+    pub fn sender_mut(&mut self) -> &mut BlockSender<W> {
+        &mut self.sender
+    }
+
+    pub fn into_sender(self) -> BlockSender<W> {
+        self.sender
+    }
+
+    /// Whether the driver was stopped by an `AbortHandle`
+    pub fn aborted(&self) -> bool {
+        self.aborted
+    }
+
+    /// Blocks submitted and not picked yet
+    pub fn queued(&mut self) -> usize {
+        self.receive();
+        self.pending.len()
+    }
+
+    // move the submitted blocks to `pending`
+    fn receive(&mut self) {
+        while let Ok(msg) = self.rx.try_recv() {
+            self.push(msg);
+        }
+    }
+
+    fn push(&mut self, msg: Msg) {
+        match msg {
+            Msg::Block(block) => self.pending.push(block),
+            Msg::Abort => self.aborted = true,
+        }
+    }
+
+    /// Write the queued blocks until every `BlockQueue` is dropped and the
+    /// queue is empty, returns the blocks sent
+    ///
+    /// `on_event` sees every write, block sent and block expired. An error
+    /// of it or of the stream, or an `AbortHandle`, stops the driver and the
+    /// queue is closed, the blocks left are aborted.
+    pub async fn run<F>(&mut self, mut on_event: F) -> io::Result<usize>
+    where
+        F: FnMut(QueueEvent) -> io::Result<()>,
+    {
+        let res = self.send_all(&mut on_event).await;
+        if res.is_err() || self.aborted {
+            self.rx.close();
+            // dropping the blocks aborts them
+            while self.rx.try_recv().is_ok() {}
+            self.pending.clear();
+        }
+        res
+    }
+
+    async fn send_all<F>(&mut self, on_event: &mut F) -> io::Result<usize>
+    where
+        F: FnMut(QueueEvent) -> io::Result<()>,
+    {
+        let mut sent = 0;
+        loop {
+            self.receive();
+            if self.aborted {
+                return Ok(sent);
+            }
+            if self.drop_expired {
+                self.expire(on_event)?;
+            }
+            let Some(index) = self.pick() else {
+                match self.rx.recv().await {
+                    Some(msg) => {
+                        self.push(msg);
+                        continue;
+                    },
+                    None => return Ok(sent),
+                }
+            };
+            let Queued { mut hdr, payload, status } = self.pending.remove(index);
+            if self.checksum && hdr.checksum.is_none() {
+                hdr.checksum = Some(payload.checksum(hdr.block_size));
+            }
+            let block = self.sender.send_with(hdr, payload, |write| on_event(QueueEvent::Write(write))).await?;
+            sent += 1;
+            on_event(QueueEvent::Sent(&block))?;
+            let _ = status.send(BlockStatus::Sent(block));
+        }
+    }
+
+    // drop the pending blocks past their deadline
+    fn expire<F>(&mut self, on_event: &mut F) -> io::Result<()>
+    where
+        F: FnMut(QueueEvent) -> io::Result<()>,
+    {
+        let now = get_current_usec();
+        let mut i = 0;
+        while i < self.pending.len() {
+            let hdr = &self.pending[i].hdr;
+            if hdr.start_timestamp + hdr.deadline * 1000 <= now {
+                let block = self.pending.remove(i);
+                on_event(QueueEvent::Expired(&block.hdr))?;
+                let _ = block.status.send(BlockStatus::Expired { at: now });
+            } else {
+                i += 1;
+            }
+        }
+        Ok(())
+    }
+
+    // index in `pending` of the block to send next
+    fn pick(&self) -> Option<usize> {
+        if self.pending.is_empty() {
+            None
+        } else if self.by_priority {
+            (0..self.pending.len()).max_by_key(|&i| (self.pending[i].hdr.priority, Reverse(i)))
+        } else {
+            Some(0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BlockReceiver;
+
+    #[tokio::test]
+    async fn handles() {
+        let (client, server) = tokio::io::duplex(100_000);
+        let (queue, driver) = block_queue(BlockSender::new(server));
+        let mut driver = driver.by_priority(true).drop_expired(true).checksum(true);
+        let mut receiver = BlockReceiver::new(client);
+
+        let low = queue.send_block(Bytes::from_static(b"low"), 1000, 1);
+        let high = queue.send_block(Bytes::from_static(b"high"), 1000, 2);
+        let hdr = BlockHeader { id: 1, start_timestamp: get_current_usec() - 2_000_000, block_size: 10, priority: 3, deadline: 1000, checksum: None };
+        // the id of the header is replaced, the producers share the ids
+        let late = queue.submit(hdr, BlockPayload::Zeros);
+        assert_eq!(13, late.id());
+        let mut equal = queue.send_block(Bytes::from_static(b"equal"), 1000, 1);
+        assert!(equal.try_status().is_none());
+        assert_eq!(4, driver.queued());
+        drop(queue);
+
+        let mut events = Vec::new();
+        let n = driver
+            .run(|event| {
+                match event {
+                    QueueEvent::Sent(sent) => events.push(("sent", sent.hdr.id)),
+                    QueueEvent::Expired(hdr) => events.push(("expired", hdr.id)),
+                    QueueEvent::Write(_) => {},
+                }
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(3, n);
+        assert_eq!(vec![("expired", 13), ("sent", 9), ("sent", 5), ("sent", 17)], events);
+        assert!(matches!(late.status().await, BlockStatus::Expired { .. }));
+        assert!(matches!(high.status().await, BlockStatus::Sent(sent) if sent.offset == 0));
+        assert!(matches!(low.status().await, BlockStatus::Sent(sent) if sent.hdr.checksum.is_some()));
+        assert!(matches!(equal.try_status(), Some(BlockStatus::Sent(_))));
+
+        drop(driver);
+        let mut blocks = Vec::new();
+        while let Some(block) = receiver.next_block().await.unwrap() {
+            blocks.push(block.unwrap());
+        }
+        assert_eq!(vec![9, 5, 17], blocks.iter().map(|block| block.id).collect::<Vec<_>>());
+        assert_eq!(4, blocks[0].block_size);
+    }
+
+    #[tokio::test]
+    async fn aborted() {
+        let (client, server) = tokio::io::duplex(100);
+        let (queue, mut driver) = block_queue(BlockSender::new(server));
+        drop(client);
+        let first = queue.send_block(Bytes::from_static(b"first"), 1000, 1);
+        let second = queue.send_block(Bytes::from_static(b"second"), 1000, 1);
+        assert!(driver.run(|_| Ok(())).await.is_err());
+        assert!(queue.is_closed());
+        assert!(matches!(first.status().await, BlockStatus::Aborted));
+        assert!(matches!(second.status().await, BlockStatus::Aborted));
+        assert!(matches!(queue.send_block(Bytes::new(), 1000, 1).status().await, BlockStatus::Aborted));
+    }
+
+    #[tokio::test]
+    async fn abort() {
+        let (client, server) = tokio::io::duplex(100_000);
+        let (queue, mut driver) = block_queue(BlockSender::new(server));
+        let mut receiver = BlockReceiver::new(client);
+        let abort = queue.abort_handle();
+        let first = queue.send_block(Bytes::from_static(b"first"), 1000, 1);
+        abort.abort();
+        let second = queue.send_block(Bytes::from_static(b"second"), 1000, 1);
+        assert_eq!(0, driver.run(|_| Ok(())).await.unwrap());
+        assert!(driver.aborted());
+        assert!(queue.is_closed());
+        assert!(matches!(first.status().await, BlockStatus::Aborted));
+        assert!(matches!(second.status().await, BlockStatus::Aborted));
+
+        driver.sender_mut().abort().await.unwrap();
+        assert!(receiver.next_block().await.unwrap().is_none());
+        assert!(receiver.aborted().is_some());
+
+        // the handle alone does not keep the driver running
+        let (queue, mut driver) = block_queue(BlockSender::new(tokio::io::sink()));
+        let abort = queue.abort_handle();
+        queue.send_block(Bytes::from_static(b"block"), 1000, 1);
+        drop(queue);
+        assert_eq!(1, driver.run(|_| Ok(())).await.unwrap());
+        assert!(!driver.aborted());
+        abort.abort();
+    }
+
+    #[tokio::test]
+    async fn empty() {
+        let (client, server) = tokio::io::duplex(1000);
+        let (queue, mut driver) = block_queue(BlockSender::new(server));
+        let mut receiver = BlockReceiver::new(client);
+        let mut empty = queue.send_block(Bytes::new(), 1000, 1);
+        assert!(matches!(empty.try_status(), Some(BlockStatus::Aborted)));
+        let block = queue.send_block(Bytes::from_static(b"block"), 1000, 1);
+        // the empty block takes no id
+        assert_eq!(5, block.id());
+        drop(queue);
+        assert_eq!(1, driver.run(|_| Ok(())).await.unwrap());
+        drop(driver);
+        let mut blocks = Vec::new();
+        while let Some(block) = receiver.next_block().await.unwrap() {
+            blocks.push(block.unwrap().id);
+        }
+        assert_eq!(vec![5], blocks);
+    }
+}
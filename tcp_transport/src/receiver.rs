@@ -0,0 +1,214 @@
+use std::future::poll_fn;
+use std::io;
+
+use bytes::{Bytes, BytesMut};
+use tokio::io::AsyncRead;
+
+use crate::streamparser::{ParseError, StreamParser};
+use crate::BlockInfo;
+
+// reserved at once for the payload of a block, a larger one grows as it
+// arrives
+const MAX_RESERVE: u64 = 1 << 20;
+
+/// A block with its payload
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub info: BlockInfo,
+    pub payload: Bytes,
+}
+
+/// Reads blocks from a stream written by a `BlockSender`
+///
+/// `next_block` drops the payloads, `next_payload` gathers the payload of
+/// every block in a `Bytes` and `next_block_with` hands it over straight
+/// from the receive buffer without a copy, for blocks too large to hold in
+/// memory. A stream is read with only one of them.
+pub struct BlockReceiver<R> {
+    stream: R,
+    parser: StreamParser,
+    // bytes read so far
+    received: u64,
+    // of the block being received by `next_payload`
+    payload: BytesMut,
+}
+
+impl<R: AsyncRead + Unpin> BlockReceiver<R> {
+    pub fn new(stream: R) -> Self {
+        BlockReceiver::with_capacity(stream, 65535)
+    }
+
+    /// A receiver whose parser buffers at most `capacity` bytes
+    pub fn with_capacity(stream: R, capacity: usize) -> Self {
+        BlockReceiver { stream, parser: StreamParser::new(capacity), received: 0, payload: BytesMut::new() }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.stream
+    }
+
+    pub fn into_inner(self) -> R {
+        self.stream
+    }
+
+    pub fn parser(&self) -> &StreamParser {
+        &self.parser
+    }
+
+    pub fn parser_mut(&mut self) -> &mut StreamParser {
+        &mut self.parser
+    }
+
+    /// Bytes read from the stream so far
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// The unix time in us the peer aborted the run at, see
+    /// `StreamParser::aborted`
+    pub fn aborted(&self) -> Option<u64> {
+        self.parser.aborted()
+    }
+
+    /// Read once from the stream into the parser, `Ok(0)` is the end of the
+    /// stream
+    ///
+    /// The parsed blocks must be taken out of the parser with `next_block`
+    /// or `StreamParser::consume`, a full parser is an error.
+    pub async fn read(&mut self) -> io::Result<usize> {
+        if self.parser.remaining() == 0 {
+            return Err(io::Error::other("the stream parser is full"));
+        }
+        let BlockReceiver { stream, parser, .. } = self;
+        let size = poll_fn(|cx| parser.poll_read_from(cx, stream)).await?;
+        self.received += size as u64;
+        Ok(size)
+    }
+
+    /// The next block or problem of the stream, `None` at the end of the
+    /// stream or once the peer aborted
+    pub async fn next_block(&mut self) -> io::Result<Option<Result<BlockInfo, ParseError>>> {
+        self.next_block_with(|_, _| {}).await
+    }
+
+    /// The next block or problem of the stream, the payload is handed to
+    /// `on_payload` a piece at a time, see `StreamParser::next_block_with`
+    pub async fn next_block_with<F>(&mut self, mut on_payload: F) -> io::Result<Option<Result<BlockInfo, ParseError>>>
+    where
+        F: FnMut(&BlockInfo, &[u8]),
+    {
+        loop {
+            if let Some(res) = self.parser.next_block_with(&mut on_payload) {
+                return Ok(Some(res));
+            }
+            if self.aborted().is_some() || self.read().await? == 0 {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// The next block with its payload or problem of the stream, `None` at
+    /// the end of the stream or once the peer aborted
+    ///
+    /// The payload of a block that is corrupted or has a clock skew is
+    /// dropped along with it.
+    pub async fn next_payload(&mut self) -> io::Result<Option<Result<Block, ParseError>>> {
+        loop {
+            let payload = &mut self.payload;
+            let res = self.parser.next_block_with(|block, piece| {
+                if payload.is_empty() {
+                    payload.reserve(block.block_size.min(MAX_RESERVE) as usize);
+                }
+                payload.extend_from_slice(piece);
+            });
+            if let Some(res) = res {
+                let payload = self.payload.split().freeze();
+                return Ok(Some(res.map(|info| Block { info, payload })));
+            }
+            if self.aborted().is_some() || self.read().await? == 0 {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dtp_utils::{get_current_usec, BlockHeader};
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn blocks() {
+        let hdr = BlockHeader { id: 5, start_timestamp: get_current_usec(), block_size: 10, priority: 1, deadline: 200, checksum: None };
+        let mut stream = hdr.to_bytes();
+        stream.resize(stream.len() + 10, 0);
+        stream.extend_from_slice(b"garbage");
+        stream.extend_from_slice(&BlockHeader { id: 9, ..hdr }.to_bytes());
+        stream.resize(stream.len() + 10, 0);
+
+        let (mut client, server) = tokio::io::duplex(16);
+        let mut receiver = BlockReceiver::with_capacity(server, 32);
+        let (_, res) = tokio::join!(
+            async move {
+                client.write_all(&stream).await.unwrap();
+            },
+            async {
+                let mut res = Vec::new();
+                while let Some(block) = receiver.next_block().await.unwrap() {
+                    res.push(block);
+                }
+                res
+            }
+        );
+        assert_eq!(5, res[0].as_ref().unwrap().id);
+        assert_eq!(Err(ParseError::Resync { skipped: 7 }), res[1]);
+        assert_eq!(9, res[2].as_ref().unwrap().id);
+        assert_eq!(3, res.len());
+        assert_eq!(2 * 54 + 7, receiver.received());
+    }
+
+    #[tokio::test]
+    async fn payloads() {
+        let data = Bytes::from((0..200_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>());
+        let hdr = |id, data: &[u8]| BlockHeader {
+            id,
+            start_timestamp: get_current_usec(),
+            block_size: data.len() as u64,
+            priority: 1,
+            deadline: 200,
+            checksum: Some(dtp_utils::crc32c::crc32c(data)),
+        };
+        let mut stream = hdr(5, &data).to_bytes();
+        stream.extend_from_slice(&data);
+        stream.extend_from_slice(&BlockHeader { checksum: Some(0), ..hdr(9, b"bad") }.to_bytes());
+        stream.extend_from_slice(b"bad");
+        stream.extend_from_slice(&hdr(13, b"abc").to_bytes());
+        stream.extend_from_slice(b"abc");
+
+        let (mut client, server) = tokio::io::duplex(1000);
+        let mut receiver = BlockReceiver::with_capacity(server, 4096);
+        let (_, res) = tokio::join!(
+            async move {
+                client.write_all(&stream).await.unwrap();
+            },
+            async {
+                let mut res = Vec::new();
+                while let Some(block) = receiver.next_payload().await.unwrap() {
+                    res.push(block);
+                }
+                res
+            }
+        );
+        let first = res[0].as_ref().unwrap();
+        assert_eq!(5, first.info.id);
+        assert_eq!(data, first.payload);
+        assert!(matches!(res[1], Err(ParseError::Corrupted { .. })));
+        assert_eq!(Bytes::from_static(b"abc"), res[2].as_ref().unwrap().payload);
+        assert_eq!(3, res.len());
+    }
+}
@@ -0,0 +1,135 @@
+use std::io;
+use std::time::Duration;
+
+use dtp_utils::{get_current_usec, BlockHeader};
+
+use crate::payload::Payload;
+use crate::queue::BlockQueue;
+use crate::schedule::Schedule;
+
+/// Id of block `index` of a schedule, unique across iterations
+pub fn block_id(index: usize) -> u64 {
+    (index * 4 + 5) as u64
+}
+
+/// Index in the schedule of the block `id`, the inverse of `block_id`
+///
+/// The queue numbers the blocks of all its producers, the index is only
+/// that of the schedule while `replay` is the only one.
+pub fn block_index(id: u64) -> usize {
+    (id.saturating_sub(5) / 4) as usize
+}
+
+/// Wait until the unix time `t` in us
+///
+/// The timer of tokio is only as precise as a millisecond, the last one is
+/// spun.
+pub async fn wait_until(t: u64) {
+    let now = get_current_usec();
+    if t > now + 2000 {
+        tokio::time::sleep(Duration::from_micros(t - now - 2000)).await;
+    }
+    while get_current_usec() < t {
+        tokio::task::yield_now().await;
+    }
+}
+
+/// Submit the blocks of `schedule` to `queue` from the unix time `start` in
+/// us on, returns the blocks submitted
+///
+/// Block `i` is submitted at `start + schedule.offset(i)` with that time as
+/// its start timestamp, the `QueueDriver` sends it as soon as the blocks
+/// before it are written. The replay stops early once the driver stopped,
+/// and the queue is dropped at the end so the driver finishes.
+pub async fn replay(queue: BlockQueue, schedule: &Schedule<'_>, payload: &mut Payload, start: u64) -> io::Result<usize> {
+    let mut index = 0;
+    while schedule.has(index) {
+        let scheduled = start + schedule.offset(index);
+        tokio::select! {
+            _ = wait_until(scheduled) => {},
+            _ = queue.closed() => break,
+        }
+        let cfg = schedule.cfg(index);
+        let hdr = BlockHeader {
+            // given by the queue
+            id: 0,
+            start_timestamp: scheduled,
+            block_size: cfg.block_size,
+            priority: cfg.priority as u64,
+            deadline: cfg.deadline as u64,
+            checksum: None,
+        };
+        let data = payload.block(index % schedule.trace_len(), cfg.block_size).map_err(|e| io::Error::other(e.to_string()))?;
+        let block = queue.submit(hdr, data);
+        debug!("{}: Submit block {} of {} bytes!", index, block.id(), hdr.wire_len() as u64 + hdr.block_size);
+        index += 1;
+        // a block already due does not wait, the driver must still get to
+        // send on a current thread runtime
+        tokio::task::yield_now().await;
+    }
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::{block_queue, QueueEvent};
+    use crate::sender::BlockSender;
+    use crate::BlockReceiver;
+    use dtp_utils::dtp_config;
+
+    #[tokio::test]
+    async fn replayed() {
+        let cfg = |gap| dtp_config { send_time_gap: gap, deadline: 200, block_size: 3000, priority: 1 };
+        let cfgs = vec![cfg(0.0), cfg(0.01), cfg(0.02)];
+        let schedule = Schedule::new(&cfgs, 1.0, Some(2), None);
+        let (client, server) = tokio::io::duplex(100_000);
+        let (queue, driver) = block_queue(BlockSender::new(server));
+        let mut driver = driver.checksum(true);
+        let mut receiver = BlockReceiver::new(client);
+        let start = get_current_usec();
+        let mut payload = Payload::parse("random").unwrap();
+        let mut sent = Vec::new();
+        let (n, sent_n) = tokio::join!(replay(queue, &schedule, &mut payload, start), driver.run(|event| {
+            if let QueueEvent::Sent(block) = event {
+                assert_eq!(sent.len(), block_index(block.hdr.id));
+                sent.push(*block);
+            }
+            Ok(())
+        }));
+        let n = n.unwrap();
+        assert_eq!(n, sent_n.unwrap());
+        assert!(sent.iter().all(|block| block.hdr.checksum.is_some()));
+        assert_eq!(6, n);
+        assert!(sent[5].first_write >= start + 60_000);
+        assert_eq!(block_id(5), sent[5].hdr.id);
+        drop(driver);
+        let mut ids = Vec::new();
+        while let Some(block) = receiver.next_block().await.unwrap() {
+            ids.push(block.unwrap().id);
+        }
+        assert_eq!((0..6).map(block_id).collect::<Vec<_>>(), ids);
+    }
+
+    #[tokio::test]
+    async fn zero_period() {
+        // every block is due at once, forever
+        let cfgs = vec![dtp_config { send_time_gap: 0.0, deadline: 200, block_size: 10, priority: 1 }];
+        let schedule = Schedule::new(&cfgs, 1.0, None, None);
+        let (queue, mut driver) = block_queue(BlockSender::new(tokio::io::sink()));
+        let mut payload = Payload::parse("zeros").unwrap();
+        let mut sent = 0;
+        let (n, res) = tokio::join!(replay(queue, &schedule, &mut payload, get_current_usec()), driver.run(|event| {
+            if let QueueEvent::Sent(_) = event {
+                sent += 1;
+                if sent == 100 {
+                    return Err(io::Error::other("enough"));
+                }
+            }
+            Ok(())
+        }));
+        assert!(res.is_err());
+        assert!(n.unwrap() >= 100);
+        assert_eq!(100, sent);
+    }
+}
@@ -0,0 +1,126 @@
+use dtp_utils::dtp_config;
+
+/// When every block is sent, with the trace replayed `loops` times or
+/// forever and its gaps multiplied by a time scale
+///
+/// Block `i` is block `i % n` of iteration `i / n` of a trace of `n` blocks,
+/// an iteration starts where the previous one sent its last block.
+pub struct Schedule<'a> {
+    cfgs: &'a [dtp_config],
+    /// send times of one iteration in us
+    gap_sum: Vec<u64>,
+    /// `None` is forever
+    loops: Option<u64>,
+    /// no block is sent from this many us on
+    duration: Option<u64>,
+}
+
+impl<'a> Schedule<'a> {
+    pub fn new(cfgs: &'a [dtp_config], time_scale: f64, loops: Option<u64>, duration: Option<u64>) -> Self {
+        let mut gap_sum = Vec::with_capacity(cfgs.len());
+        let mut sum = 0;
+        for cfg in cfgs {
+            // the same as without a time scale when it is 1
+            sum += ((cfg.send_time_gap * 1_000_000.0) as f64 * time_scale) as u64;
+            gap_sum.push(sum);
+        }
+        Schedule { cfgs, gap_sum, loops, duration }
+    }
+
+    /// Length of an iteration in us
+    pub fn period(&self) -> u64 {
+        self.gap_sum.last().copied().unwrap_or(0)
+    }
+
+    /// Blocks of one iteration
+    pub fn trace_len(&self) -> usize {
+        self.cfgs.len()
+    }
+
+    pub fn iteration(&self, i: usize) -> u64 {
+        (i / self.cfgs.len()) as u64
+    }
+
+    pub fn cfg(&self, i: usize) -> &dtp_config {
+        &self.cfgs[i % self.cfgs.len()]
+    }
+
+    /// Send time of block `i` in us since the start
+    pub fn offset(&self, i: usize) -> u64 {
+        self.iteration(i) * self.period() + self.gap_sum[i % self.cfgs.len()]
+    }
+
+    /// Whether block `i` is sent at all
+    pub fn has(&self, i: usize) -> bool {
+        !self.cfgs.is_empty()
+            && self.loops.is_none_or(|loops| self.iteration(i) < loops)
+            && self.duration.is_none_or(|duration| self.offset(i) < duration)
+    }
+
+    /// Blocks whose send time has come `elapsed` us after the start
+    pub fn due(&self, elapsed: u64) -> usize {
+        let n = self.cfgs.len();
+        if !self.has(0) {
+            return 0;
+        }
+        let period = self.period();
+        if period == 0 {
+            // every iteration is sent at once
+            return self.loops.map_or(usize::MAX, |loops| loops as usize * n);
+        }
+        // no block is sent at or after the duration
+        let elapsed = self.duration.map_or(elapsed, |duration| elapsed.min(duration - 1));
+        // iterations sent completely, then a binary search in the next one
+        let full = self.loops.map_or(elapsed / period, |loops| (elapsed / period).min(loops));
+        let (mut lo, mut hi) = (full as usize * n, (full as usize + 1) * n);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if self.has(mid) && self.offset(mid) <= elapsed {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        lo
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfgs() -> Vec<dtp_config> {
+        let cfg = |gap| dtp_config { send_time_gap: gap, deadline: 200, block_size: 1000, priority: 1 };
+        vec![cfg(0.1), cfg(0.2), cfg(0.7)]
+    }
+
+    #[test]
+    fn looped() {
+        let cfgs = cfgs();
+        let schedule = Schedule::new(&cfgs, 1.0, Some(2), None);
+        assert_eq!(1_000_000, schedule.period());
+        let offsets: Vec<u64> = (0..6).map(|i| schedule.offset(i)).collect();
+        assert_eq!(vec![100_000, 300_000, 1_000_000, 1_100_000, 1_300_000, 2_000_000], offsets);
+        assert!(schedule.has(5));
+        assert!(!schedule.has(6));
+        assert_eq!(1, schedule.iteration(3));
+        assert_eq!(0, schedule.due(0));
+        assert_eq!(3, schedule.due(1_000_000));
+        assert_eq!(4, schedule.due(1_200_000));
+        assert_eq!(6, schedule.due(10_000_000));
+    }
+
+    #[test]
+    fn scaled() {
+        let cfgs = cfgs();
+        let schedule = Schedule::new(&cfgs, 0.5, None, Some(1_200_000));
+        assert_eq!(500_000, schedule.period());
+        assert_eq!(650_000, schedule.offset(4));
+        // the block at 1.5s is not sent
+        assert!(schedule.has(7));
+        assert!(!schedule.has(8));
+        assert_eq!(8, schedule.due(u64::MAX / 4));
+        assert_eq!(7, schedule.due(1_100_000));
+        assert_eq!(Schedule::new(&cfgs, 1.0, None, None).due(5_050_000), 15);
+    }
+}
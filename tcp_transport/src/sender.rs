@@ -0,0 +1,171 @@
+use std::future::poll_fn;
+use std::io;
+use std::pin::Pin;
+use std::task::Poll;
+
+use dtp_utils::{abort_message, get_current_usec, BlockHeader};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::block::BlockBuf;
+use crate::payload::BlockPayload;
+
+/// A block written to the stream, times are unix time in us
+#[derive(Clone, Copy, Debug)]
+pub struct Sent {
+    pub hdr: BlockHeader,
+    /// Position of the header in the stream
+    pub offset: u64,
+    /// the first byte is handed to the stream
+    pub first_write: u64,
+    /// the last byte is written
+    pub last_write: u64,
+    /// writes that waited for room in the stream
+    pub would_block: u64,
+}
+
+/// One write of a block to the stream
+#[derive(Clone, Copy, Debug)]
+pub struct Write<'a> {
+    pub hdr: &'a BlockHeader,
+    /// Position of `data` in the stream
+    pub offset: u64,
+    pub data: &'a [u8],
+    /// `data` starts with the header
+    pub starts: bool,
+    /// `data` ends the block
+    pub ends: bool,
+}
+
+/// Writes blocks to a stream, each a `BlockHeader` followed by its payload
+///
+/// The payload is generated a chunk at a time while it is written, so a
+/// block of any size takes little memory.
+pub struct BlockSender<W> {
+    stream: W,
+    // bytes written so far
+    offset: u64,
+}
+
+impl<W: AsyncWrite + Unpin> BlockSender<W> {
+    pub fn new(stream: W) -> Self {
+        BlockSender { stream, offset: 0 }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.stream
+    }
+
+    pub fn into_inner(self) -> W {
+        self.stream
+    }
+
+    /// Bytes written to the stream so far, headers included
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Write a block, `hdr.block_size` bytes of `payload` follow the header
+    pub async fn send(&mut self, hdr: BlockHeader, payload: BlockPayload) -> io::Result<Sent> {
+        self.send_with(hdr, payload, |_| Ok(())).await
+    }
+
+    /// Write a block and hand every write to `on_write`, an error of it
+    /// stops the block
+    ///
+    /// A write of 0 bytes is an `io::ErrorKind::WriteZero` error, the peer
+    /// is gone.
+    pub async fn send_with<F>(&mut self, hdr: BlockHeader, payload: BlockPayload, mut on_write: F) -> io::Result<Sent>
+    where
+        F: FnMut(Write) -> io::Result<()>,
+    {
+        let mut block = BlockBuf::new(hdr, payload);
+        let mut sent = Sent { hdr, offset: self.offset, first_write: 0, last_write: 0, would_block: 0 };
+        let mut first_write = None;
+        while block.remaining() > 0 {
+            let starts = block.is_new();
+            let remaining = block.remaining();
+            let data = block.pending();
+            let stream = &mut self.stream;
+            let would_block = &mut sent.would_block;
+            let size = poll_fn(|cx| match Pin::new(&mut *stream).poll_write(cx, data) {
+                Poll::Pending => {
+                    *would_block += 1;
+                    Poll::Pending
+                },
+                ready => ready,
+            })
+            .await?;
+            if size == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            first_write.get_or_insert_with(get_current_usec);
+            on_write(Write { hdr: &hdr, offset: self.offset, data: &data[..size], starts, ends: size as u64 == remaining })?;
+            self.offset += size as u64;
+            block.advance(size);
+        }
+        sent.last_write = get_current_usec();
+        sent.first_write = first_write.unwrap_or(sent.last_write);
+        Ok(sent)
+    }
+
+    /// Flush the stream and shut down its write side
+    pub async fn shutdown(&mut self) -> io::Result<()> {
+        self.stream.shutdown().await
+    }
+
+    /// Tell the peer the run is aborted and shut down the write side, no
+    /// block may be half written
+    pub async fn abort(&mut self) -> io::Result<()> {
+        self.stream.write_all(&abort_message(get_current_usec())).await?;
+        self.offset += dtp_utils::ABORT_SIZE as u64;
+        self.shutdown().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BlockReceiver;
+
+    #[tokio::test]
+    async fn roundtrip() {
+        let (client, server) = tokio::io::duplex(1000);
+        let mut sender = BlockSender::new(server);
+        let mut receiver = BlockReceiver::new(client);
+        let data = BlockPayload::Bytes { data: b"abc".to_vec().into(), pos: 0 };
+        let hdr = BlockHeader { id: 5, start_timestamp: get_current_usec(), block_size: 100_000, priority: 1, deadline: 200, checksum: Some(data.checksum(100_000)) };
+        let (sent, received) = tokio::join!(
+            async {
+                let mut writes = 0;
+                let sent = sender.send_with(hdr, data, |write| {
+                    assert_eq!(writes == 0, write.starts);
+                    writes += 1;
+                    Ok(())
+                }).await.unwrap();
+                sender.send(BlockHeader { id: 9, block_size: 10, checksum: None, ..hdr }, BlockPayload::Zeros).await.unwrap();
+                sender.shutdown().await.unwrap();
+                (sent, writes)
+            },
+            async {
+                let mut blocks = Vec::new();
+                while let Some(res) = receiver.next_block().await.unwrap() {
+                    blocks.push(res.unwrap());
+                }
+                blocks
+            }
+        );
+        let (sent, writes) = sent;
+        // the duplex holds 1000 bytes at a time
+        assert!(writes > 100);
+        assert!(sent.would_block > 0);
+        assert_eq!(0, sent.offset);
+        assert_eq!(vec![5, 9], received.iter().map(|block| block.id).collect::<Vec<_>>());
+        assert_eq!(100_000, received[0].block_size);
+        assert_eq!(100_048, received[1].offset);
+        assert_eq!(100_048 + 44 + 10, sender.offset());
+    }
+}
@@ -0,0 +1,611 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::task::{Context, Poll};
+
+use dtp_utils::crc32c::Crc32c;
+use dtp_utils::{get_current_usec, header_len, parse_abort, BlockHeader, ABORT_MAGIC, ABORT_SIZE, HEADER_SIZE, MAX_HEADER_SIZE};
+use tokio::io::AsyncRead;
+
+use crate::loopbytes::{LoopBytes, PushError};
+use crate::BlockInfo;
+
+/// Problems found in the received stream
+///
+/// None of them is fatal: the parser drops the bad bytes and goes on with the
+/// next block header it can find.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseError {
+    /// `skipped` bytes were thrown away before the next block magic was found
+    Resync { skipped: usize },
+    InvalidBlockSize { id: u64, block_size: u64 },
+    InvalidPriority { id: u64, priority: u64 },
+    InvalidDeadline { id: u64, deadline: u64 },
+    /// The block ended before it started according to the local clock.
+    /// The block is still complete, its bct is set to 0.
+    ClockSkew(BlockInfo),
+    /// The CRC-32C of the payload is not the checksum in the header
+    Corrupted { block: BlockInfo, actual: u32 },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Resync { skipped } => write!(f, "skipped {} bytes to find the next block header", skipped),
+            ParseError::InvalidBlockSize { id, block_size } => write!(f, "block {}: invalid block size {}", id, block_size),
+            ParseError::InvalidPriority { id, priority } => write!(f, "block {}: invalid priority {}", id, priority),
+            ParseError::InvalidDeadline { id, deadline } => write!(f, "block {}: invalid deadline {}", id, deadline),
+            ParseError::ClockSkew(block) => write!(
+                f,
+                "block {}: end timestamp {} is before start timestamp {}",
+                block.id, block.end_timestamp, block.start_timestamp
+            ),
+            ParseError::Corrupted { block, actual } => write!(
+                f,
+                "block {}: payload checksum {:08x}, expected {:08x}",
+                block.id, actual, block.checksum.unwrap_or_default()
+            ),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+pub struct StreamParser {
+    // sliding window over the bytes that may be the next header
+    hdr: [u8; MAX_HEADER_SIZE],
+    hdr_len: usize,
+    skipped: usize,
+    has_hdr: bool,
+    // payload bytes of the current block that are not received yet
+    remaining: u64,
+    // of the payload received so far, if the header has a checksum
+    crc: Option<Crc32c>,
+    cur_block: BlockInfo,
+    bytes: LoopBytes,
+    // bytes taken out of `bytes` since the start of the stream
+    offset: u64,
+    // unix time in us of the abort message of the peer
+    aborted: Option<u64>,
+}
+
+impl Default for StreamParser {
+    fn default() -> StreamParser {
+        StreamParser::new(65535)
+    }
+}
+
+impl StreamParser {
+    pub fn new(size: usize) -> Self {
+        StreamParser {
+            hdr: [0; MAX_HEADER_SIZE],
+            hdr_len: 0,
+            skipped: 0,
+            has_hdr: false,
+            remaining: 0,
+            crc: None,
+            cur_block: BlockInfo::default(),
+            bytes: LoopBytes::new(size + 1),
+            offset: 0,
+            aborted: None,
+        }
+    }
+
+    #[must_use = "bytes that do not fit are not received"]
+    pub fn recv(&mut self, buf: &[u8], size: usize) -> usize {
+        self.bytes.push(buf, size)
+    }
+
+    /// Receive all of `buf` or nothing at all
+    pub fn try_recv(&mut self, buf: &[u8]) -> Result<(), PushError> {
+        self.bytes.try_push(buf)
+    }
+
+    /// Bytes that can still be received before the buffer is full
+    pub fn remaining(&self) -> usize {
+        self.bytes.max_remaining()
+    }
+
+    /// Read from `reader` straight into the parser buffer, see `LoopBytes::read_from`
+    pub fn read_from<R: Read>(&mut self, reader: &mut R) -> io::Result<usize> {
+        self.bytes.read_from(reader)
+    }
+
+    /// Read once from an async `reader` straight into the parser buffer, see
+    /// `LoopBytes::poll_read_from`
+    pub fn poll_read_from<R: AsyncRead + Unpin>(&mut self, cx: &mut Context<'_>, reader: &mut R) -> Poll<io::Result<usize>> {
+        self.bytes.poll_read_from(cx, reader)
+    }
+
+    /// The last `n` received bytes that are not parsed yet
+    pub fn last_received(&self, n: usize) -> (&[u8], &[u8]) {
+        self.bytes.last_slices(n)
+    }
+
+    /// The block whose payload is being received, if its header is parsed
+    pub fn current_block(&self) -> Option<&BlockInfo> {
+        if self.has_hdr {
+            Some(&self.cur_block)
+        } else {
+            None
+        }
+    }
+
+    /// Payload bytes of the current block that are not parsed yet
+    pub fn block_remaining(&self) -> u64 {
+        if self.has_hdr {
+            self.remaining
+        } else {
+            0
+        }
+    }
+
+    /// The unix time in us the peer aborted the run at, once its abort
+    /// message is parsed
+    ///
+    /// Nothing after the message is parsed.
+    pub fn aborted(&self) -> Option<u64> {
+        self.aborted
+    }
+
+    /// Parse all the complete blocks in the buffer
+    pub fn consume(&mut self) -> Vec<Result<BlockInfo, ParseError>> {
+        let mut ret = vec![];
+        while let Some(res) = self.next_block() {
+            ret.push(res);
+        }
+        ret
+    }
+
+    /// Parse the next complete block, `None` if more bytes are needed
+    pub fn next_block(&mut self) -> Option<Result<BlockInfo, ParseError>> {
+        self.next_block_with(|_, _| {})
+    }
+
+    /// Parse the next complete block and hand its payload to `on_payload`
+    /// in order, a piece at a time as it is parsed
+    ///
+    /// The pieces are slices of the receive buffer, nothing is copied. They
+    /// are handed over before the checksum of the block is verified, a
+    /// corrupted block is only known once it is complete.
+    pub fn next_block_with<F>(&mut self, mut on_payload: F) -> Option<Result<BlockInfo, ParseError>>
+    where
+        F: FnMut(&BlockInfo, &[u8]),
+    {
+        loop {
+            if self.aborted.is_some() {
+                return None;
+            }
+            if !self.has_hdr {
+                // the magic tells whether a checksum follows, or whether it
+                // is no block at all
+                let window = &self.hdr[..self.hdr_len];
+                let len = if window.starts_with(&ABORT_MAGIC) {
+                    ABORT_SIZE
+                } else {
+                    header_len(window).unwrap_or(HEADER_SIZE)
+                };
+                if self.hdr_len < len {
+                    let popped = self.bytes.pop(&mut self.hdr[self.hdr_len..len], len - self.hdr_len);
+                    self.hdr_len += popped;
+                    self.offset += popped as u64;
+                    if popped == 0 {
+                        return None;
+                    }
+                    // the new bytes may have completed a magic
+                    continue;
+                }
+                let abort = parse_abort(&self.hdr[..self.hdr_len]);
+                let hdr = match BlockHeader::from_bytes(&self.hdr[..len]) {
+                    Some(hdr) => hdr,
+                    // the peer only aborts at a block boundary, an abort
+                    // magic found while resyncing is a coincidence
+                    None if abort.is_some() && self.skipped == 0 => {
+                        debug!("the peer aborted at {:?}", abort);
+                        self.aborted = abort;
+                        return None;
+                    }
+                    None => {
+                        self.skip_byte();
+                        continue;
+                    }
+                };
+                if self.skipped > 0 {
+                    // report the lost bytes before the block that follows them
+                    let skipped = self.skipped;
+                    self.skipped = 0;
+                    return Some(Err(ParseError::Resync { skipped }));
+                }
+                if let Err(e) = self.parse_hdr(&hdr) {
+                    // the magic may have been a coincidence, keep searching
+                    self.skip_byte();
+                    return Some(Err(e));
+                }
+                debug!("parse block: {:?}", self.cur_block);
+                // the window is only longer than a header while the magic of
+                // a rejected longer header is skipped, and no magic starts
+                // within a magic
+                debug_assert_eq!(len, self.hdr_len);
+                self.hdr_len = 0;
+                self.has_hdr = true;
+            } else {
+                // the payload of a large block is never buffered completely
+                let want = self.remaining.min(self.bytes.size() as u64) as usize;
+                let (first, second) = self.bytes.as_slices();
+                let n = want.min(first.len());
+                for piece in [&first[..n], &second[..want - n]].iter().filter(|piece| !piece.is_empty()) {
+                    if let Some(ref mut crc) = self.crc {
+                        crc.update(piece);
+                    }
+                    on_payload(&self.cur_block, piece);
+                }
+                let dropped = self.bytes.drop(want);
+                self.remaining -= dropped as u64;
+                self.offset += dropped as u64;
+                if self.remaining > 0 {
+                    debug!("remain: {}", self.remaining);
+                    return None;
+                }
+                self.has_hdr = false;
+                let mut block = self.cur_block;
+                self.cur_block = BlockInfo::default();
+                block.end_timestamp = get_current_usec();
+                debug!("final block: {:?}", block);
+                if let Some(crc) = self.crc.take() {
+                    let actual = crc.finish();
+                    if Some(actual) != block.checksum {
+                        return Some(Err(ParseError::Corrupted { block, actual }));
+                    }
+                }
+                if block.end_timestamp < block.start_timestamp {
+                    return Some(Err(ParseError::ClockSkew(block)));
+                }
+                block.bct = (block.end_timestamp - block.start_timestamp) / 1000;
+                return Some(Ok(block));
+            }
+        }
+    }
+
+    fn parse_hdr(&mut self, hdr: &BlockHeader) -> Result<(), ParseError> {
+        let id = hdr.id;
+        if hdr.block_size == 0 {
+            return Err(ParseError::InvalidBlockSize { id, block_size: hdr.block_size });
+        }
+        if hdr.priority > i32::MAX as u64 {
+            return Err(ParseError::InvalidPriority { id, priority: hdr.priority });
+        }
+        if hdr.deadline > i32::MAX as u64 {
+            return Err(ParseError::InvalidDeadline { id, deadline: hdr.deadline });
+        }
+        self.cur_block = BlockInfo {
+            id,
+            start_timestamp: hdr.start_timestamp,
+            block_size: hdr.block_size,
+            priority: hdr.priority as i32,
+            deadline: hdr.deadline as i32,
+            offset: self.offset - hdr.wire_len() as u64,
+            checksum: hdr.checksum,
+            ..BlockInfo::default()
+        };
+        self.remaining = hdr.block_size;
+        self.crc = hdr.checksum.map(|_| Crc32c::new());
+        Ok(())
+    }
+
+    fn skip_byte(&mut self) {
+        self.hdr.copy_within(1.., 0);
+        self.hdr_len -= 1;
+        self.skipped += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dtp_utils::crc32c::crc32c;
+    use proptest::prelude::*;
+
+    fn block(id: u64, block_size: u64) -> Vec<u8> {
+        let hdr = BlockHeader {
+            id,
+            start_timestamp: get_current_usec(),
+            block_size,
+            priority: 1,
+            deadline: 200,
+            checksum: None,
+        };
+        let mut bytes = hdr.to_bytes().to_vec();
+        bytes.resize(HEADER_SIZE + block_size as usize, 0);
+        bytes
+    }
+
+    // a block with a checksum of the payload
+    fn checked_block(id: u64, payload: &[u8]) -> Vec<u8> {
+        let hdr = BlockHeader {
+            id,
+            start_timestamp: get_current_usec(),
+            block_size: payload.len() as u64,
+            priority: 1,
+            deadline: 200,
+            checksum: Some(crc32c(payload)),
+        };
+        let mut bytes = hdr.to_bytes();
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    fn feed(parser: &mut StreamParser, stream: &[u8], chunk: usize) -> Vec<Result<BlockInfo, ParseError>> {
+        let mut ret = vec![];
+        for buf in stream.chunks(chunk) {
+            let mut total_size = 0;
+            while total_size < buf.len() {
+                total_size += parser.recv(&buf[total_size..], buf.len() - total_size);
+                ret.append(&mut parser.consume());
+            }
+        }
+        ret
+    }
+
+    #[test]
+    fn recv() {
+        let mut parser = StreamParser::new(5);
+        let buf: [u8; 3] = [0, 1, 2];
+        assert_eq!(3, parser.recv(&buf, 3));
+        assert_eq!(2, parser.recv(&buf, 3));
+        assert_eq!(Err(PushError::Full), parser.try_recv(&buf[..1]));
+        assert_eq!(0, parser.remaining());
+    }
+
+    #[test]
+    fn blocks() {
+        let mut stream = block(5, 100);
+        stream.append(&mut block(9, 1));
+        stream.append(&mut block(13, 3000));
+        for chunk in [1, 7, 44, 1000, 10000].iter() {
+            let mut parser = StreamParser::new(128);
+            let ids: Vec<u64> = feed(&mut parser, &stream, *chunk)
+                .into_iter()
+                .map(|res| res.unwrap().id)
+                .collect();
+            assert_eq!(vec![5, 9, 13], ids);
+        }
+        let mut parser = StreamParser::default();
+        let res = feed(&mut parser, &stream[..HEADER_SIZE * 2 + 150], 1000);
+        assert_eq!(144, res[1].as_ref().unwrap().offset);
+        assert_eq!(189, parser.current_block().unwrap().offset);
+    }
+
+    #[test]
+    fn resync() {
+        let mut stream = vec![0xff; 3];
+        stream.append(&mut block(5, 10));
+        // garbage that contains a partial magic
+        stream.extend_from_slice(b"xDTP");
+        stream.append(&mut block(9, 10));
+        let mut parser = StreamParser::default();
+        let res = feed(&mut parser, &stream, 5);
+        assert_eq!(4, res.len());
+        assert_eq!(Err(ParseError::Resync { skipped: 3 }), res[0]);
+        assert_eq!(5, res[1].as_ref().unwrap().id);
+        assert_eq!(Err(ParseError::Resync { skipped: 4 }), res[2]);
+        assert_eq!(9, res[3].as_ref().unwrap().id);
+    }
+
+    #[test]
+    fn invalid_header() {
+        let mut stream = block(5, 0);
+        stream.append(&mut block(9, 10));
+        let mut parser = StreamParser::default();
+        let res = feed(&mut parser, &stream, 100);
+        assert_eq!(3, res.len());
+        assert_eq!(Err(ParseError::InvalidBlockSize { id: 5, block_size: 0 }), res[0]);
+        assert_eq!(Err(ParseError::Resync { skipped: HEADER_SIZE }), res[1]);
+        assert_eq!(9, res[2].as_ref().unwrap().id);
+    }
+
+    #[test]
+    fn large_block() {
+        // more than 4 GB, only the header is in memory
+        let size = 5_000_000_000;
+        let mut parser = StreamParser::new(1000);
+        let hdr = BlockHeader { id: 7, start_timestamp: get_current_usec(), block_size: size, priority: 1, deadline: 200, checksum: None };
+        assert!(feed(&mut parser, &hdr.to_bytes(), 100).is_empty());
+        assert!(feed(&mut parser, &[0; 3000], 500).is_empty());
+        assert_eq!(Some(size), parser.current_block().map(|block| block.block_size));
+        assert_eq!(size - 3000, parser.block_remaining());
+    }
+
+    #[test]
+    fn clock_skew() {
+        let hdr = BlockHeader {
+            id: 5,
+            start_timestamp: u64::MAX,
+            block_size: 10,
+            priority: 1,
+            deadline: 200,
+            checksum: None,
+        };
+        let mut stream = hdr.to_bytes().to_vec();
+        stream.resize(HEADER_SIZE + 10, 0);
+        let mut parser = StreamParser::default();
+        match feed(&mut parser, &stream, 100).as_slice() {
+            [Err(ParseError::ClockSkew(block))] => {
+                assert_eq!(5, block.id);
+                assert_eq!(0, block.bct);
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn checksum() {
+        let payload: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+        let mut stream = checked_block(5, &payload);
+        stream.append(&mut block(9, 10));
+        let mut corrupted = checked_block(13, &payload[..100]);
+        corrupted[MAX_HEADER_SIZE + 50] ^= 1;
+        stream.append(&mut corrupted);
+        stream.append(&mut checked_block(17, &payload[..1]));
+        for chunk in [1, 3, 48, 1000, 10000].iter() {
+            let mut parser = StreamParser::new(128);
+            let res = feed(&mut parser, &stream, *chunk);
+            assert_eq!(4, res.len());
+            assert_eq!(Some(crc32c(&payload)), res[0].as_ref().unwrap().checksum);
+            assert_eq!(None, res[1].as_ref().unwrap().checksum);
+            match &res[2] {
+                Err(ParseError::Corrupted { block, actual }) => {
+                    assert_eq!(13, block.id);
+                    assert_ne!(block.checksum, Some(*actual));
+                },
+                res => panic!("unexpected result: {:?}", res),
+            }
+            let last = res[3].as_ref().unwrap();
+            assert_eq!(17, last.id);
+            assert_eq!((3000 + MAX_HEADER_SIZE + 10 + HEADER_SIZE + 100 + MAX_HEADER_SIZE) as u64, last.offset);
+        }
+    }
+
+    #[test]
+    fn abort() {
+        let mut stream = block(5, 100);
+        stream.extend_from_slice(&dtp_utils::abort_message(42));
+        stream.append(&mut block(9, 10));
+        for chunk in [1, 12, 1000].iter() {
+            let mut parser = StreamParser::new(128);
+            let res = feed(&mut parser, &stream, *chunk);
+            assert_eq!(5, res[0].as_ref().unwrap().id);
+            assert_eq!(1, res.len());
+            assert_eq!(Some(42), parser.aborted());
+        }
+        // skipped like any garbage while resyncing
+        let mut stream = block(5, 100);
+        stream.extend_from_slice(b"xy");
+        stream.extend_from_slice(&dtp_utils::abort_message(42));
+        stream.append(&mut block(9, 10));
+        for chunk in [1, 12, 1000].iter() {
+            let mut parser = StreamParser::new(128);
+            let res = feed(&mut parser, &stream, *chunk);
+            assert_eq!(Err(ParseError::Resync { skipped: 2 + dtp_utils::ABORT_SIZE }), res[1]);
+            assert_eq!(9, res[2].as_ref().unwrap().id);
+            assert_eq!(3, res.len());
+            assert_eq!(None, parser.aborted());
+        }
+    }
+
+    #[test]
+    fn payload_pieces() {
+        let payload: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+        let mut stream = checked_block(5, &payload);
+        stream.extend_from_slice(b"garbage");
+        stream.append(&mut checked_block(9, &payload[7..20]));
+        for chunk in [1, 48, 100, 10000].iter() {
+            let mut parser = StreamParser::new(128);
+            let mut received: Vec<(u64, Vec<u8>)> = Vec::new();
+            let mut ids = Vec::new();
+            for buf in stream.chunks(*chunk) {
+                let mut total_size = 0;
+                while total_size < buf.len() {
+                    total_size += parser.recv(&buf[total_size..], buf.len() - total_size);
+                    while let Some(res) = parser.next_block_with(|block, piece| match received.last_mut() {
+                        Some((id, data)) if *id == block.id => data.extend_from_slice(piece),
+                        _ => received.push((block.id, piece.to_vec())),
+                    }) {
+                        if let Ok(block) = res {
+                            ids.push(block.id);
+                        }
+                    }
+                }
+            }
+            assert_eq!(vec![5, 9], ids);
+            assert_eq!(vec![(5, payload.clone()), (9, payload[7..20].to_vec())], received);
+        }
+    }
+
+    // feed `stream` cut at the given chunk sizes
+    fn feed_chunks(parser: &mut StreamParser, stream: &[u8], chunks: &[usize]) -> Vec<Result<BlockInfo, ParseError>> {
+        let mut ret = vec![];
+        let mut offset = 0;
+        for chunk in chunks.iter().cycle() {
+            if offset >= stream.len() {
+                break;
+            }
+            let end = std::cmp::min(offset + chunk, stream.len());
+            ret.append(&mut feed(parser, &stream[offset..end], end - offset));
+            offset = end;
+        }
+        ret
+    }
+
+    fn garbage() -> impl Strategy<Value = Vec<u8>> {
+        // anything but a block magic, or an abort magic that would end the
+        // stream at a block boundary
+        prop::collection::vec(any::<u8>(), 0..60).prop_filter("contains a magic", |bytes| {
+            !bytes.windows(4).any(|w| dtp_utils::header_len(w).is_some() || w == ABORT_MAGIC)
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn any_chunking(sizes in prop::collection::vec(1..3000u64, 0..20),
+                        chunks in prop::collection::vec(1..5000usize, 1..20),
+                        capacity in 1..200usize) {
+            let mut stream = vec![];
+            for (i, size) in sizes.iter().enumerate() {
+                stream.append(&mut block(i as u64, *size));
+            }
+            let mut parser = StreamParser::new(capacity);
+            let res = feed_chunks(&mut parser, &stream, &chunks);
+            let ids: Vec<u64> = res.into_iter().map(|res| res.unwrap().id).collect();
+            prop_assert_eq!(ids, (0..sizes.len() as u64).collect::<Vec<u64>>());
+        }
+
+        #[test]
+        fn garbage_between_blocks(blocks in prop::collection::vec((1..500u64, garbage()), 0..20),
+                                  chunks in prop::collection::vec(1..1000usize, 1..20)) {
+            let mut stream = vec![];
+            for (i, (size, garbage)) in blocks.iter().enumerate() {
+                stream.extend_from_slice(garbage);
+                stream.append(&mut block(i as u64, *size));
+            }
+            let mut parser = StreamParser::default();
+            let res = feed_chunks(&mut parser, &stream, &chunks);
+            let mut ids = vec![];
+            let mut skipped = 0;
+            for r in res {
+                match r {
+                    Ok(block) => ids.push(block.id),
+                    Err(ParseError::Resync { skipped: n }) => skipped += n,
+                    Err(e) => prop_assert!(false, "unexpected error {:?}", e),
+                }
+            }
+            prop_assert_eq!(ids, (0..blocks.len() as u64).collect::<Vec<u64>>());
+            let total: usize = blocks.iter().map(|(_, garbage)| garbage.len()).sum();
+            prop_assert_eq!(skipped, total);
+        }
+
+        #[test]
+        fn checked_blocks(blocks in prop::collection::vec((prop::collection::vec(any::<u8>(), 1..500), any::<bool>()), 0..20),
+                          chunks in prop::collection::vec(1..1000usize, 1..20)) {
+            let mut stream = vec![];
+            for (i, (payload, checked)) in blocks.iter().enumerate() {
+                if *checked {
+                    stream.append(&mut checked_block(i as u64, payload));
+                } else {
+                    stream.append(&mut block(i as u64, payload.len() as u64));
+                }
+            }
+            let mut parser = StreamParser::default();
+            let res = feed_chunks(&mut parser, &stream, &chunks);
+            let ids: Vec<u64> = res.into_iter().map(|res| res.unwrap().id).collect();
+            prop_assert_eq!(ids, (0..blocks.len() as u64).collect::<Vec<u64>>());
+        }
+
+        #[test]
+        fn arbitrary_bytes(stream in prop::collection::vec(any::<u8>(), 0..2000),
+                           chunks in prop::collection::vec(1..100usize, 1..10)) {
+            // must never panic
+            let mut parser = StreamParser::new(64);
+            feed_chunks(&mut parser, &stream, &chunks);
+        }
+    }
+}